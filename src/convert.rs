@@ -1,7 +1,20 @@
-//! Format conversion between JSON, YAML, TOML, and TOON
+//! Format conversion between JSON, JSON5, YAML, TOML, TOON, CSV, TSV, XML, INI
+//! and dotenv
 
+use crate::delimited;
+use crate::detect::has_json5_syntax;
+use crate::diagnostic::Diagnostic;
+use crate::dotenv;
+use crate::ini;
+use crate::json5;
+use crate::val::{json_to_val, key_string, sort_keys, val_to_json, DeVal, SerVal};
+use crate::xml;
 use crate::OqError;
+use jaq_json::Val;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
+use std::path::Path;
 
 /// Supported input formats
 #[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
@@ -11,21 +24,39 @@ pub enum InputFormat {
     Auto,
     /// JSON format
     Json,
+    /// JSON5 or JSONC (comments, trailing commas, unquoted keys)
+    Json5,
     /// YAML format
     Yaml,
     /// TOML format
     Toml,
     /// TOON format
     Toon,
+    /// CSV, with a header row
+    Csv,
+    /// TSV, with a header row
+    Tsv,
+    /// XML format
+    Xml,
+    /// INI format (git config, systemd units, ...)
+    Ini,
+    /// Dotenv format (`KEY=value` lines)
+    Env,
 }
 
 /// Internal format representation
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Format {
     Json,
+    Json5,
     Yaml,
     Toml,
     Toon,
+    Csv,
+    Tsv,
+    Xml,
+    Ini,
+    Env,
 }
 
 impl Format {
@@ -33,9 +64,15 @@ impl Format {
     pub fn name(&self) -> &'static str {
         match self {
             Format::Json => "json",
+            Format::Json5 => "json5",
             Format::Yaml => "yaml",
             Format::Toml => "toml",
             Format::Toon => "toon",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Xml => "xml",
+            Format::Ini => "ini",
+            Format::Env => "env",
         }
     }
 
@@ -43,12 +80,59 @@ impl Format {
     pub fn from_name(name: &str) -> Option<Self> {
         match name.to_lowercase().as_str() {
             "json" => Some(Format::Json),
+            "json5" | "jsonc" => Some(Format::Json5),
             "yaml" | "yml" => Some(Format::Yaml),
             "toml" => Some(Format::Toml),
             "toon" => Some(Format::Toon),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "xml" => Some(Format::Xml),
+            "ini" => Some(Format::Ini),
+            "env" | "dotenv" => Some(Format::Env),
             _ => None,
         }
     }
+
+    /// Format for a file extension (without the dot), ignoring case
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "json" | "jsonl" | "ndjson" => Some(Format::Json),
+            "json5" | "jsonc" => Some(Format::Json5),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "toon" => Some(Format::Toon),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "xml" | "svg" => Some(Format::Xml),
+            "ini" | "service" | "socket" | "timer" | "mount" | "desktop" => Some(Format::Ini),
+            "env" => Some(Format::Env),
+            _ => None,
+        }
+    }
+
+    /// Format for a file path, based on its extension (or its name, for
+    /// dotfiles such as `.editorconfig` and `.env.local`)
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(".editorconfig" | ".gitconfig" | ".gitmodules") => Some(Format::Ini),
+            Some(name) if name == ".env" || name.starts_with(".env.") => Some(Format::Env),
+            _ => path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(Self::from_extension),
+        }
+    }
+
+    /// Format for a file path, as [`Format::from_path`], except that JSON
+    /// files with comments or trailing commas (such as `tsconfig.json`) are
+    /// read as JSON5
+    pub fn from_path_and_input(path: impl AsRef<Path>, input: &str) -> Option<Self> {
+        match Self::from_path(path)? {
+            Format::Json if has_json5_syntax(input) => Some(Format::Json5),
+            format => Some(format),
+        }
+    }
 }
 
 /// Output format options
@@ -56,6 +140,8 @@ impl Format {
 pub enum OutputFormat {
     /// JSON format
     Json,
+    /// JSON5, with unquoted keys where they are identifiers
+    Json5,
     /// YAML format
     Yaml,
     /// TOML format
@@ -63,15 +149,31 @@ pub enum OutputFormat {
     /// TOON format (default)
     #[default]
     Toon,
+    /// CSV, a row per array element
+    Csv,
+    /// TSV, a row per array element
+    Tsv,
+    /// XML format
+    Xml,
+    /// INI format
+    Ini,
+    /// Shell variable assignments, a line per key
+    Env,
 }
 
 impl From<OutputFormat> for Format {
     fn from(f: OutputFormat) -> Self {
         match f {
             OutputFormat::Json => Format::Json,
+            OutputFormat::Json5 => Format::Json5,
             OutputFormat::Yaml => Format::Yaml,
             OutputFormat::Toml => Format::Toml,
             OutputFormat::Toon => Format::Toon,
+            OutputFormat::Csv => Format::Csv,
+            OutputFormat::Tsv => Format::Tsv,
+            OutputFormat::Xml => Format::Xml,
+            OutputFormat::Ini => Format::Ini,
+            OutputFormat::Env => Format::Env,
         }
     }
 }
@@ -81,135 +183,163 @@ impl InputFormat {
     pub fn detect(&self, input: &str) -> Format {
         match self {
             InputFormat::Json => Format::Json,
+            InputFormat::Json5 => Format::Json5,
             InputFormat::Yaml => Format::Yaml,
             InputFormat::Toml => Format::Toml,
             InputFormat::Toon => Format::Toon,
+            InputFormat::Csv => Format::Csv,
+            InputFormat::Tsv => Format::Tsv,
+            InputFormat::Xml => Format::Xml,
+            InputFormat::Ini => Format::Ini,
+            InputFormat::Env => Format::Env,
             InputFormat::Auto => detect_format(input),
         }
     }
 }
 
-/// Auto-detect format based on content heuristics
+/// Auto-detect format from the content
+///
+/// See [`explain_format`](crate::explain_format) for how candidates are
+/// scored and trial-parsed.
 pub fn detect_format(input: &str) -> Format {
-    let trimmed = input.trim();
-    let lines: Vec<&str> = trimmed.lines().collect();
-
-    // Empty input defaults to JSON
-    if trimmed.is_empty() {
-        return Format::Json;
-    }
-
-    // Check for TOML section headers first (looks like JSON array but isn't)
-    let first_line = lines.first().map(|l| l.trim()).unwrap_or("");
-    if first_line.starts_with('[')
-        && first_line.ends_with(']')
-        && !first_line.contains(',')
-        && !first_line.contains(':')
-    {
-        return Format::Toml;
-    }
-
-    // JSON starts with { or [
-    if trimmed.starts_with('{') || trimmed.starts_with('[') {
-        return Format::Json;
-    }
-
-    // Check for TOML patterns
-    let has_toml_sections = lines.iter().any(|line| {
-        let l = line.trim();
-        l.starts_with('[')
-            && l.ends_with(']')
-            && !l.starts_with("[[")
-            && !l.contains(',')
-            && !l.contains(':')
-    });
-
-    let has_toml_array_tables = lines
-        .iter()
-        .any(|line| line.trim().starts_with("[[") && line.trim().ends_with("]]"));
-
-    let has_toml_assignments = lines.iter().any(|line| {
-        let l = line.trim();
-        if l.is_empty() || l.starts_with('#') || l.starts_with('[') {
-            return false;
-        }
-        if let Some(eq_pos) = l.find(" = ") {
-            let key = &l[..eq_pos];
-            !key.is_empty()
-                && key
-                    .chars()
-                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '"')
-        } else {
-            false
-        }
-    });
+    crate::detect::explain_format(input).format
+}
 
-    if has_toml_sections || has_toml_array_tables || has_toml_assignments {
-        return Format::Toml;
+/// Parse input in the detected format
+pub fn parse_input(input: &str, format: Format) -> Result<Value, OqError> {
+    match format {
+        Format::Toml => toml_to_json(deserialize(input, format)?),
+        _ => deserialize(input, format),
     }
+}
 
-    // YAML detection
-    if trimmed.starts_with("---") {
-        return Format::Yaml;
+/// Parse input in the detected format directly into a jaq value
+pub fn parse_input_val(input: &str, format: Format) -> Result<Val, OqError> {
+    match format {
+        // Go through toml::Value so that datetimes become plain strings
+        Format::Toml => toml_to_val(deserialize(input, format)?),
+        Format::Csv | Format::Tsv => delimited::parse(input, format, &InputOptions::default()),
+        Format::Xml => xml::parse(input),
+        Format::Ini => ini::parse(input, &InputOptions::default()),
+        Format::Env => dotenv::parse(input),
+        _ => deserialize(input, format).map(|DeVal(val)| val),
     }
+}
 
-    let has_yaml_list = lines.iter().any(|line| {
-        let l = line.trim();
-        l.starts_with("- ") || l == "-"
-    });
-
-    let has_yaml_multiline = lines.iter().any(|line| {
-        let l = line.trim();
-        l.ends_with(": |") || l.ends_with(": >") || l.ends_with(": |+") || l.ends_with(": |-")
-    });
+/// Deserialize a single document
+pub(crate) fn deserialize<T: DeserializeOwned>(input: &str, format: Format) -> Result<T, OqError> {
+    match format {
+        Format::Json => {
+            serde_json::from_str(input).map_err(|e| Diagnostic::from_json(input, &e).into())
+        }
+        Format::Json5 => json5::from_str(input),
+        Format::Yaml => {
+            serde_yaml::from_str(input).map_err(|e| Diagnostic::from_yaml(input, &e).into())
+        }
+        Format::Toml => toml::from_str(input).map_err(|e| Diagnostic::from_toml(input, &e).into()),
+        Format::Toon => {
+            toon_format::decode_default(input).map_err(|e| Diagnostic::from_toon(input, &e).into())
+        }
+        // Formats without a serde deserializer are read as values first
+        Format::Csv | Format::Tsv | Format::Xml | Format::Ini | Format::Env => {
+            let value = val_to_json(&parse_input_val(input, format)?)?;
+            serde_json::from_value(value).map_err(OqError::JsonParse)
+        }
+    }
+}
 
-    let has_yaml_nesting = lines
-        .iter()
-        .any(|line| line.starts_with("  ") && line.trim().contains(": "));
+/// Iterator over the documents of a multi-document input
+pub type Documents<'a> = Box<dyn Iterator<Item = Result<Value, OqError>> + 'a>;
 
-    if has_yaml_list || has_yaml_multiline || has_yaml_nesting {
-        return Format::Yaml;
-    }
+/// Iterator over the documents of a multi-document input, as jaq values
+pub type ValDocuments<'a> = Box<dyn Iterator<Item = Result<Val, OqError>> + 'a>;
 
-    // Check for JSON literals
-    if trimmed == "true" || trimmed == "false" || trimmed == "null" {
-        return Format::Json;
+/// Parse every document in the input
+///
+/// JSON yields each concatenated or newline-delimited value (JSON Lines),
+/// YAML yields each `---`-separated document. TOML, TOON, CSV, TSV, XML, INI
+/// and dotenv inputs always contain a single document. The iterator stops
+/// after the first error.
+pub fn parse_stream(input: &str, format: Format) -> Documents<'_> {
+    match format {
+        Format::Json | Format::Yaml => deserialize_stream(input, format),
+        _ => Box::new(std::iter::once(parse_input(input, format))),
     }
+}
 
-    if trimmed.parse::<f64>().is_ok() {
-        return Format::Json;
+/// Parse every document in the input directly into jaq values
+///
+/// See [`parse_stream`] for how documents are split.
+pub fn parse_stream_val(input: &str, format: Format) -> ValDocuments<'_> {
+    match format {
+        Format::Json | Format::Yaml => {
+            Box::new(deserialize_stream(input, format).map(|doc| doc.map(|DeVal(val)| val)))
+        }
+        _ => Box::new(std::iter::once(parse_input_val(input, format))),
     }
+}
 
-    // TOON: simple key: value without YAML complexity
-    let has_colon_space = lines.iter().any(|line| {
-        let l = line.trim();
-        !l.is_empty() && !l.starts_with('#') && l.contains(": ")
-    });
-
-    if has_colon_space {
-        return Format::Toon;
+/// Parse every document in the input directly into jaq values, with input
+/// options
+pub fn parse_stream_val_with<'a>(
+    input: &'a str,
+    format: Format,
+    options: &InputOptions,
+) -> ValDocuments<'a> {
+    match format {
+        Format::Csv | Format::Tsv => {
+            Box::new(std::iter::once(delimited::parse(input, format, options)))
+        }
+        Format::Ini => Box::new(std::iter::once(ini::parse(input, options))),
+        _ => parse_stream_val(input, format),
     }
+}
 
-    // Default to JSON
-    Format::Json
+/// Split plain text into one string per line (like jq `-R`)
+///
+/// Line terminators are removed; a final line without one is still yielded.
+pub fn parse_raw_lines(input: &str) -> ValDocuments<'_> {
+    Box::new(input.split_inclusive('\n').map(|line| {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        Ok(Val::from(line.to_string()))
+    }))
 }
 
-/// Parse input in the detected format
-pub fn parse_input(input: &str, format: Format) -> Result<Value, OqError> {
+/// Deserialize every document of a JSON or YAML stream
+pub(crate) fn deserialize_stream<'a, T: DeserializeOwned + 'a>(
+    input: &'a str,
+    format: Format,
+) -> Box<dyn Iterator<Item = Result<T, OqError>> + 'a> {
     match format {
-        Format::Json => serde_json::from_str(input).map_err(OqError::JsonParse),
-        Format::Yaml => serde_yaml::from_str(input).map_err(OqError::YamlParse),
-        Format::Toml => {
-            let toml_value: toml::Value =
-                toml::from_str(input).map_err(|e| OqError::TomlParse(e.to_string()))?;
-            toml_to_json(toml_value)
+        Format::Yaml => {
+            let documents = serde_yaml::Deserializer::from_str(input);
+            stop_after_error(documents.map(move |doc| {
+                T::deserialize(doc).map_err(|e| Diagnostic::from_yaml(input, &e).into())
+            }))
         }
-        Format::Toon => {
-            toon_format::decode_default(input).map_err(|e| OqError::ToonParse(e.to_string()))
+        _ => {
+            let values = serde_json::Deserializer::from_str(input).into_iter::<T>();
+            stop_after_error(
+                values.map(move |r| r.map_err(|e| Diagnostic::from_json(input, &e).into())),
+            )
         }
     }
 }
 
+/// End a stream of results once it has yielded an error
+pub(crate) fn stop_after_error<'a, T: 'a>(
+    results: impl Iterator<Item = Result<T, OqError>> + 'a,
+) -> Box<dyn Iterator<Item = Result<T, OqError>> + 'a> {
+    let mut failed = false;
+    Box::new(results.map_while(move |result| {
+        if failed {
+            return None;
+        }
+        failed = result.is_err();
+        Some(result)
+    }))
+}
+
 /// Parse input with auto-detection
 pub fn parse_auto(input: &str) -> Result<Value, OqError> {
     let format = detect_format(input);
@@ -251,7 +381,7 @@ fn json_to_toml(value: &Value) -> Result<toml::Value, OqError> {
             } else if let Some(f) = n.as_f64() {
                 Ok(toml::Value::Float(f))
             } else {
-                Err(OqError::TomlParse("Invalid number".to_string()))
+                Err(OqError::Encode(Format::Toml, "invalid number".to_string()))
             }
         }
         Value::String(s) => Ok(toml::Value::String(s.clone())),
@@ -269,42 +399,369 @@ fn json_to_toml(value: &Value) -> Result<toml::Value, OqError> {
     }
 }
 
+/// Convert TOML value to jaq value
+fn toml_to_val(value: toml::Value) -> Result<Val, OqError> {
+    match value {
+        toml::Value::String(s) => Ok(Val::from(s)),
+        toml::Value::Integer(i) => Ok(isize::try_from(i)
+            .map(Val::from)
+            .unwrap_or_else(|_| Val::from(i as f64))),
+        toml::Value::Float(f) => Ok(Val::from(f)),
+        toml::Value::Boolean(b) => Ok(Val::Bool(b)),
+        toml::Value::Datetime(dt) => Ok(Val::from(dt.to_string())),
+        toml::Value::Array(arr) => arr.into_iter().map(toml_to_val).collect(),
+        toml::Value::Table(table) => {
+            let mut map = jaq_json::Map::default();
+            for (k, v) in table {
+                map.insert(Val::from(k), toml_to_val(v)?);
+            }
+            Ok(Val::obj(map))
+        }
+    }
+}
+
+/// Convert jaq value to TOML value
+fn val_to_toml(value: &Val) -> Result<toml::Value, OqError> {
+    match value {
+        Val::Null => Ok(toml::Value::String("null".to_string())),
+        Val::Bool(b) => Ok(toml::Value::Boolean(*b)),
+        Val::Num(n) => {
+            let text = n.to_string();
+            if let Ok(i) = text.parse::<i64>() {
+                Ok(toml::Value::Integer(i))
+            } else if let Ok(f) = text.parse::<f64>() {
+                Ok(toml::Value::Float(f))
+            } else {
+                Err(OqError::Encode(Format::Toml, "invalid number".to_string()))
+            }
+        }
+        Val::Str(..) => Ok(toml::Value::String(key_string(value))),
+        Val::Arr(arr) => {
+            let toml_arr: Result<Vec<_>, _> = arr.iter().map(val_to_toml).collect();
+            Ok(toml::Value::Array(toml_arr?))
+        }
+        Val::Obj(obj) => {
+            let mut table = toml::map::Map::new();
+            for (k, v) in obj.iter() {
+                table.insert(key_string(k), val_to_toml(v)?);
+            }
+            Ok(toml::Value::Table(table))
+        }
+    }
+}
+
+/// Options for parsing inputs
+///
+/// They only affect the formats they apply to: `header` applies to CSV and
+/// TSV, and `infer_types` to CSV, TSV and INI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputOptions {
+    /// The first row of CSV and TSV names the columns
+    pub header: bool,
+    /// Turn CSV and TSV fields and INI values that look like numbers,
+    /// booleans or nothing into numbers, booleans and null
+    pub infer_types: bool,
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        Self {
+            header: true,
+            infer_types: false,
+        }
+    }
+}
+
+impl InputOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn with_infer_types(mut self, infer_types: bool) -> Self {
+        self.infer_types = infer_types;
+        self
+    }
+}
+
 /// Convert a JSON value to the specified format
 pub fn encode_to_format(value: &Value, format: Format) -> Result<String, OqError> {
     match format {
-        Format::Json => serde_json::to_string_pretty(value).map_err(OqError::JsonParse),
-        Format::Yaml => serde_yaml::to_string(value).map_err(OqError::YamlParse),
+        Format::Toml => to_toml(value),
+        _ => serialize(value, format),
+    }
+}
+
+/// Indentation for pretty-printed output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    /// A number of spaces per level (0 gives compact JSON, like jq)
+    Spaces(usize),
+    /// One tab per level
+    Tab,
+}
+
+/// Options that control how values are encoded
+///
+/// Options apply where the format allows them: YAML and TOML have a fixed
+/// layout, TOON can only indent with spaces, `ascii` affects JSON only,
+/// `header` and `columns` affect CSV and TSV only, and `export` and
+/// `env_separator` affect env output only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputOptions {
+    /// Indentation for JSON, TOON and XML
+    pub indent: Indent,
+    /// Print JSON on a single line
+    pub compact: bool,
+    /// Sort the keys of every object, mapping and table
+    pub sort_keys: bool,
+    /// Escape non-ASCII characters in JSON as `\uXXXX`
+    pub ascii: bool,
+    /// Write a header row before CSV and TSV rows of objects
+    pub header: bool,
+    /// The CSV and TSV columns of objects, in order (default: the keys of
+    /// the first object)
+    pub columns: Option<Vec<String>>,
+    /// Prefix env assignments with `export`
+    pub export: bool,
+    /// Joins the keys of nested objects into env variable names
+    pub env_separator: String,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            indent: Indent::Spaces(2),
+            compact: false,
+            sort_keys: false,
+            ascii: false,
+            header: true,
+            columns: None,
+            export: false,
+            env_separator: "__".to_string(),
+        }
+    }
+}
+
+impl OutputOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_indent(mut self, indent: Indent) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    pub fn with_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub fn with_sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    pub fn with_ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn with_columns(mut self, columns: Vec<String>) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    pub fn with_export(mut self, export: bool) -> Self {
+        self.export = export;
+        self
+    }
+
+    pub fn with_env_separator(mut self, separator: impl Into<String>) -> Self {
+        self.env_separator = separator.into();
+        self
+    }
+}
+
+/// Convert a JSON value to the specified format with output options
+pub fn encode_to_format_with(
+    value: &Value,
+    format: Format,
+    options: &OutputOptions,
+) -> Result<String, OqError> {
+    encode_val_to_format_with(&json_to_val(value.clone()), format, options)
+}
+
+/// Convert a jaq value to the specified format with output options
+pub fn encode_val_to_format_with(
+    value: &Val,
+    format: Format,
+    options: &OutputOptions,
+) -> Result<String, OqError> {
+    let sorted;
+    let value = if options.sort_keys {
+        sorted = sort_keys(value);
+        &sorted
+    } else {
+        value
+    };
+
+    match format {
+        Format::Json => {
+            let compact = || {
+                serde_json::to_string(&SerVal(value))
+                    .map_err(|e| OqError::Encode(Format::Json, e.to_string()))
+            };
+            let json = match options.indent {
+                _ if options.compact => compact()?,
+                Indent::Spaces(0) => compact()?,
+                Indent::Spaces(n) => to_json_indented(value, &" ".repeat(n))?,
+                Indent::Tab => to_json_indented(value, "\t")?,
+            };
+            Ok(if options.ascii {
+                escape_non_ascii(&json)
+            } else {
+                json
+            })
+        }
+        Format::Json5 => {
+            let indent = match options.indent {
+                _ if options.compact => None,
+                Indent::Spaces(0) => None,
+                Indent::Spaces(n) => Some(" ".repeat(n)),
+                Indent::Tab => Some("\t".to_string()),
+            };
+            let json5 = json5::to_string(&SerVal(value), indent.as_deref())?;
+            Ok(if options.ascii {
+                escape_non_ascii(&json5)
+            } else {
+                json5
+            })
+        }
+        Format::Toon => {
+            let spaces = match options.indent {
+                Indent::Spaces(n) if n > 0 => n,
+                _ => 2,
+            };
+            let toon_options = toon_format::EncodeOptions::new().with_spaces(spaces);
+            toon_format::encode(&SerVal(value), &toon_options)
+                .map_err(|e| OqError::Encode(Format::Toon, e.to_string()))
+        }
+        Format::Csv | Format::Tsv => {
+            delimited::encode(value, format, options.columns.as_deref(), options.header)
+        }
+        Format::Xml => {
+            let indent = match options.indent {
+                _ if options.compact => None,
+                Indent::Spaces(0) => None,
+                Indent::Spaces(n) => Some((b' ', n)),
+                Indent::Tab => Some((b'\t', 1)),
+            };
+            xml::encode(value, indent)
+        }
+        Format::Env => dotenv::encode(value, &options.env_separator, options.export),
+        Format::Yaml | Format::Toml | Format::Ini => encode_val_to_format(value, format),
+    }
+}
+
+/// Pretty-print JSON with the given indentation string
+fn to_json_indented(value: &Val, indent: &str) -> Result<String, OqError> {
+    let mut buf = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    SerVal(value)
+        .serialize(&mut serializer)
+        .map_err(|e| OqError::Encode(Format::Json, e.to_string()))?;
+    Ok(String::from_utf8(buf).expect("serde_json writes UTF-8"))
+}
+
+/// Replace every non-ASCII character with its `\uXXXX` escape
+///
+/// Only valid for JSON text, where such characters can appear only inside
+/// strings.
+fn escape_non_ascii(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    out
+}
+
+/// Convert a jaq value to the specified format
+pub fn encode_val_to_format(value: &Val, format: Format) -> Result<String, OqError> {
+    match format {
         Format::Toml => {
-            let toml_value = json_to_toml(value)?;
-            toml::to_string_pretty(&toml_value).map_err(|e| OqError::TomlParse(e.to_string()))
+            let toml_value = val_to_toml(value)?;
+            toml::to_string_pretty(&toml_value)
+                .map_err(|e| OqError::Encode(Format::Toml, e.to_string()))
         }
-        Format::Toon => to_toon(value),
+        Format::Csv | Format::Tsv => delimited::encode(value, format, None, true),
+        Format::Xml => xml::encode(value, Some((b' ', 2))),
+        Format::Ini => ini::encode(value),
+        Format::Env => dotenv::encode(value, "__", false),
+        _ => serialize(&SerVal(value), format),
     }
 }
 
+/// Serialize a value with the encoder for the given format
+fn serialize<T: Serialize>(value: &T, format: Format) -> Result<String, OqError> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+        Format::Json5 => return json5::to_string(value, Some("  ")),
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+        Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+        Format::Toon => toon_format::encode_default(value).map_err(|e| e.to_string()),
+        // Formats without a serde serializer are written from values
+        Format::Csv | Format::Tsv | Format::Xml | Format::Ini | Format::Env => {
+            match serde_json::to_value(value) {
+                Ok(value) => return encode_val_to_format(&json_to_val(value), format),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    }
+    .map_err(|e| OqError::Encode(format, e))
+}
+
 /// Convert a JSON value to TOON string
 pub fn to_toon(value: &Value) -> Result<String, OqError> {
-    toon_format::encode_default(value).map_err(|e| OqError::ToonParse(e.to_string()))
+    toon_format::encode_default(value).map_err(|e| OqError::Encode(Format::Toon, e.to_string()))
 }
 
 /// Convert a JSON value to JSON string
 pub fn to_json(value: &Value, pretty: bool) -> Result<String, OqError> {
     if pretty {
-        serde_json::to_string_pretty(value).map_err(OqError::JsonParse)
+        serde_json::to_string_pretty(value)
     } else {
-        serde_json::to_string(value).map_err(OqError::JsonParse)
+        serde_json::to_string(value)
     }
+    .map_err(|e| OqError::Encode(Format::Json, e.to_string()))
 }
 
 /// Convert a JSON value to YAML string
 pub fn to_yaml(value: &Value) -> Result<String, OqError> {
-    serde_yaml::to_string(value).map_err(OqError::YamlParse)
+    serde_yaml::to_string(value).map_err(|e| OqError::Encode(Format::Yaml, e.to_string()))
 }
 
 /// Convert a JSON value to TOML string
 pub fn to_toml(value: &Value) -> Result<String, OqError> {
     let toml_value = json_to_toml(value)?;
-    toml::to_string_pretty(&toml_value).map_err(|e| OqError::TomlParse(e.to_string()))
+    toml::to_string_pretty(&toml_value).map_err(|e| OqError::Encode(Format::Toml, e.to_string()))
 }
 
 #[cfg(test)]
@@ -322,8 +779,8 @@ mod tests {
     }
 
     #[test]
-    fn test_detect_toon_object() {
-        assert_eq!(detect_format("name: Ada\nage: 30"), Format::Toon);
+    fn test_detect_key_value_as_yaml() {
+        assert_eq!(detect_format("name: Ada\nage: 30"), Format::Yaml);
     }
 
     #[test]
@@ -380,6 +837,192 @@ mod tests {
         assert_eq!(result["age"], 30);
     }
 
+    #[test]
+    fn test_parse_stream_json_lines() {
+        let input = "{\"id\": 1}\n{\"id\": 2}\n{\"id\": 3}\n";
+        let docs: Vec<Value> = parse_stream(input, Format::Json)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[2]["id"], 3);
+    }
+
+    #[test]
+    fn test_parse_stream_concatenated_json() {
+        let input = r#"{"a":1}{"b":2}[3] 4"#;
+        let docs: Vec<Value> = parse_stream(input, Format::Json)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            docs,
+            vec![
+                serde_json::json!({"a": 1}),
+                serde_json::json!({"b": 2}),
+                serde_json::json!([3]),
+                serde_json::json!(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_stream_yaml_documents() {
+        let input = "kind: Service\n---\nkind: Deployment\n";
+        assert_eq!(detect_format(input), Format::Yaml);
+        let docs: Vec<Value> = parse_stream(input, Format::Yaml)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[1]["kind"], "Deployment");
+    }
+
+    #[test]
+    fn test_parse_stream_stops_after_error() {
+        let docs: Vec<_> = parse_stream("1 2 } 3", Format::Json).collect();
+        assert_eq!(docs.len(), 3);
+        assert!(docs[2].is_err());
+    }
+
+    #[test]
+    fn test_parse_input_val_matches_json() {
+        let cases = [
+            (
+                Format::Json,
+                r#"{"name": "Ada", "langs": ["en", "fr"], "age": 36}"#,
+            ),
+            (Format::Yaml, "name: Ada\nlangs: [en, fr]\nage: 36"),
+            (
+                Format::Toml,
+                "name = \"Ada\"\nlangs = [\"en\", \"fr\"]\nage = 36",
+            ),
+            (Format::Toon, "name: Ada\nlangs[2]: en,fr\nage: 36"),
+        ];
+        for (format, input) in cases {
+            let val = parse_input_val(input, format).unwrap();
+            let json = parse_input(input, format).unwrap();
+            assert_eq!(crate::val_to_json(&val).unwrap(), json, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_toml_datetime_is_string() {
+        let val = parse_input_val("when = 1979-05-27T07:32:00Z", Format::Toml).unwrap();
+        assert_eq!(
+            crate::val_to_json(&val).unwrap(),
+            serde_json::json!({"when": "1979-05-27T07:32:00Z"})
+        );
+    }
+
+    #[test]
+    fn test_encode_val_matches_json() {
+        let json = serde_json::json!({"name": "Ada", "tags": ["a"], "n": 1.5, "none": null});
+        let val = crate::json_to_val(json.clone());
+        for format in [Format::Json, Format::Yaml, Format::Toml, Format::Toon] {
+            assert_eq!(
+                encode_val_to_format(&val, format).unwrap(),
+                encode_to_format(&json, format).unwrap(),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_errors_are_output_errors() {
+        // TOML documents are tables
+        let value = serde_json::json!([1, 2]);
+        for result in [
+            to_toml(&value),
+            encode_to_format(&value, Format::Toml),
+            encode_val_to_format(&crate::json_to_val(value.clone()), Format::Toml),
+        ] {
+            assert!(matches!(result, Err(OqError::Encode(Format::Toml, _))));
+        }
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("Cargo.toml"), Some(Format::Toml));
+        assert_eq!(Format::from_path("dir/app.YML"), Some(Format::Yaml));
+        assert_eq!(Format::from_path("events.ndjson"), Some(Format::Json));
+        assert_eq!(Format::from_path("data.toon"), Some(Format::Toon));
+        assert_eq!(Format::from_path("notes.txt"), None);
+        assert_eq!(Format::from_path("Makefile"), None);
+    }
+
+    #[test]
+    fn test_from_path_and_input() {
+        let input = "title: x";
+        assert_eq!(
+            Format::from_path_and_input("config.yaml", input),
+            Some(Format::Yaml)
+        );
+        assert_eq!(
+            Format::from_path_and_input("config.toon", input),
+            Some(Format::Toon)
+        );
+        // Without a known extension, the content decides
+        assert_eq!(Format::from_path_and_input("config", input), None);
+        assert_eq!(
+            Format::from_path_and_input("tsconfig.json", "{\"a\": 1, // x\n}"),
+            Some(Format::Json5)
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_lines() {
+        let lines: Vec<Val> = parse_raw_lines("a b\n\nlast")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected: Vec<Val> = ["a b", "", "last"].map(|s| Val::from(s.to_string())).into();
+        assert_eq!(lines, expected);
+        assert_eq!(parse_raw_lines("x\n").count(), 1);
+        assert_eq!(parse_raw_lines("").count(), 0);
+    }
+
+    #[test]
+    fn test_output_options_indent() {
+        let val = parse_input_val(r#"{"a": [1]}"#, Format::Json).unwrap();
+        let tab = OutputOptions::new().with_indent(Indent::Tab);
+        assert_eq!(
+            encode_val_to_format_with(&val, Format::Json, &tab).unwrap(),
+            "{\n\t\"a\": [\n\t\t1\n\t]\n}"
+        );
+        let zero = OutputOptions::new().with_indent(Indent::Spaces(0));
+        assert_eq!(
+            encode_val_to_format_with(&val, Format::Json, &zero).unwrap(),
+            r#"{"a":[1]}"#
+        );
+    }
+
+    #[test]
+    fn test_output_options_sort_keys() {
+        let val = parse_input_val(r#"{"b": 1, "a": {"d": 2, "c": 3}}"#, Format::Json).unwrap();
+        let sorted = OutputOptions::new().with_sort_keys(true);
+        assert_eq!(
+            encode_val_to_format_with(&val, Format::Yaml, &sorted).unwrap(),
+            "a:\n  c: 3\n  d: 2\nb: 1\n"
+        );
+        assert_eq!(
+            encode_val_to_format_with(&val, Format::Toml, &sorted).unwrap(),
+            "b = 1\n\n[a]\nc = 3\nd = 2\n"
+        );
+        // Without sorting, document order is kept
+        assert_eq!(
+            encode_val_to_format_with(&val, Format::Yaml, &OutputOptions::new()).unwrap(),
+            "b: 1\na:\n  d: 2\n  c: 3\n"
+        );
+    }
+
+    #[test]
+    fn test_output_options_ascii() {
+        let val = Val::from("caf\u{e9} \u{1f600}".to_string());
+        let ascii = OutputOptions::new().with_ascii(true);
+        assert_eq!(
+            encode_val_to_format_with(&val, Format::Json, &ascii).unwrap(),
+            r#""caf\u00e9 \ud83d\ude00""#
+        );
+    }
+
     #[test]
     fn test_roundtrip_formats() {
         let json = serde_json::json!({"name": "Ada", "active": true});
@@ -0,0 +1,221 @@
+//! Delimited text: CSV and TSV
+//!
+//! A CSV or TSV input is one document, an array with an object per row. The
+//! keys are the fields of the header row, or the column indexes (`"0"`,
+//! `"1"`, ...) when there is no header. Fields are strings unless types are
+//! inferred, in which case numbers, `true`, `false` and empty fields become
+//! numbers, booleans and null.
+//!
+//! Encoding writes a row per element of an array (or a single row for any
+//! other value). Objects are written in the order of the columns, taken
+//! from the first object unless they are given, after a header row; arrays
+//! are written as-is. Fields are quoted as RFC 4180 requires.
+
+use crate::convert::{Format, InputOptions};
+use crate::diagnostic::Diagnostic;
+use crate::val::{key_string, DeVal};
+use crate::OqError;
+use jaq_json::Val;
+
+/// Field delimiter of a delimited format
+fn delimiter(format: Format) -> u8 {
+    match format {
+        Format::Tsv => b'\t',
+        _ => b',',
+    }
+}
+
+/// Parse CSV or TSV into an array of row objects
+pub(crate) fn parse(input: &str, format: Format, options: &InputOptions) -> Result<Val, OqError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter(format))
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+
+    let mut header: Option<Vec<Val>> = None;
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| Diagnostic::from_csv(input, format, &e))?;
+        if options.header && header.is_none() {
+            header = Some(
+                record
+                    .iter()
+                    .map(|key| Val::from(key.to_string()))
+                    .collect(),
+            );
+            continue;
+        }
+        let keys = header.as_deref().unwrap_or_default();
+        let row = record.iter().enumerate().map(|(i, field)| {
+            let key = keys
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| Val::from(i.to_string()));
+            let value = if options.infer_types {
+                infer(field)
+            } else {
+                Val::from(field.to_string())
+            };
+            (key, value)
+        });
+        rows.push(Val::obj(row.collect()));
+    }
+    Ok(Val::from_iter(rows))
+}
+
+/// The value a field looks like, for `--infer-types`
+pub(crate) fn infer(field: &str) -> Val {
+    match field {
+        "" => Val::Null,
+        "true" => Val::Bool(true),
+        "false" => Val::Bool(false),
+        // JSON number syntax, so that e.g. `007` and `+1` stay strings
+        _ if field.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+            && field.ends_with(|c: char| c.is_ascii_digit()) =>
+        {
+            match serde_json::from_str::<DeVal>(field) {
+                Ok(DeVal(number @ Val::Num(_))) => number,
+                _ => Val::from(field.to_string()),
+            }
+        }
+        _ => Val::from(field.to_string()),
+    }
+}
+
+/// Encode a value as CSV or TSV rows
+///
+/// `columns` orders the fields of objects; without it, the keys of the first
+/// object are used. With `header`, a header row comes first when there are
+/// objects.
+pub(crate) fn encode(
+    value: &Val,
+    format: Format,
+    columns: Option<&[String]>,
+    header: bool,
+) -> Result<String, OqError> {
+    let rows: Vec<&Val> = match value {
+        Val::Arr(rows) => rows.iter().collect(),
+        other => vec![other],
+    };
+    let columns: Vec<String> = match columns {
+        Some(columns) => columns.to_vec(),
+        None => rows
+            .iter()
+            .find_map(|row| match row {
+                Val::Obj(obj) => Some(obj.keys().map(key_string).collect()),
+                _ => None,
+            })
+            .unwrap_or_default(),
+    };
+
+    let encode_error = |e: csv::Error| OqError::Encode(format, e.to_string());
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter(format))
+        .flexible(true)
+        .from_writer(Vec::new());
+    let has_objects = rows.iter().any(|row| matches!(row, Val::Obj(_)));
+    if header && has_objects {
+        writer.write_record(&columns).map_err(encode_error)?;
+    }
+    for row in rows {
+        let fields: Vec<String> = match row {
+            Val::Obj(obj) => columns
+                .iter()
+                .map(|column| {
+                    obj.get(&Val::from(column.clone()))
+                        .map_or_else(String::new, field)
+                })
+                .collect(),
+            Val::Arr(values) => values.iter().map(field).collect(),
+            other => vec![field(other)],
+        };
+        writer.write_record(&fields).map_err(encode_error)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The text of a field: strings as-is, null as nothing and anything else
+/// as JSON
+fn field(value: &Val) -> String {
+    match value {
+        Val::Null => String::new(),
+        other => key_string(other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::val::val_to_json;
+    use serde_json::json;
+
+    fn parse_json(input: &str, format: Format, options: &InputOptions) -> serde_json::Value {
+        val_to_json(&parse(input, format, options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_with_header() {
+        let input = "name,age,city\nAda,36,\"London, UK\"\nBob,007,\n";
+        assert_eq!(
+            parse_json(input, Format::Csv, &InputOptions::new()),
+            json!([
+                {"name": "Ada", "age": "36", "city": "London, UK"},
+                {"name": "Bob", "age": "007", "city": ""},
+            ])
+        );
+        let options = InputOptions::new().with_infer_types(true);
+        assert_eq!(
+            parse_json(input, Format::Csv, &options),
+            json!([
+                {"name": "Ada", "age": 36, "city": "London, UK"},
+                {"name": "Bob", "age": "007", "city": null},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_without_header() {
+        let options = InputOptions::new()
+            .with_header(false)
+            .with_infer_types(true);
+        assert_eq!(
+            parse_json("a\t1.5\ttrue\nb\t-2\n", Format::Tsv, &options),
+            json!([
+                {"0": "a", "1": 1.5, "2": true},
+                {"0": "b", "1": -2},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_encode_objects() {
+        let value = json_val(json!([
+            {"name": "Ada", "note": "says \"hi\", twice", "tags": ["a"]},
+            {"note": "line\nbreak", "name": "Bob", "age": 40},
+        ]));
+        assert_eq!(
+            encode(&value, Format::Csv, None, true).unwrap(),
+            "name,note,tags\nAda,\"says \"\"hi\"\", twice\",\"[\"\"a\"\"]\"\nBob,\"line\nbreak\",\n"
+        );
+        let columns = ["age".to_string(), "name".to_string()];
+        assert_eq!(
+            encode(&value, Format::Tsv, Some(&columns), false).unwrap(),
+            "\tAda\n40\tBob\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_arrays_and_scalars() {
+        let value = json_val(json!([[1, "a b", null], "x"]));
+        assert_eq!(
+            encode(&value, Format::Csv, None, true).unwrap(),
+            "1,a b,\nx\n"
+        );
+    }
+
+    fn json_val(value: serde_json::Value) -> Val {
+        crate::val::json_to_val(value)
+    }
+}
@@ -0,0 +1,551 @@
+//! Content-based format detection
+//!
+//! Every format is scored from the evidence found in the input, then the
+//! candidates are trial-parsed from the most to the least likely, and the
+//! first one that parses wins. [`explain_format`] reports the scores and the
+//! reason for the choice. [`explain_format_start`] detects the format of a
+//! large input from its start alone, from the scores only.
+
+use crate::convert::{deserialize, deserialize_stream, Format};
+use crate::json5;
+use crate::OqError;
+use serde::de::IgnoredAny;
+use std::fmt;
+
+/// Score of one candidate format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatScore {
+    pub format: Format,
+    /// Higher is more likely; 0 means there is no evidence for the format
+    pub score: u32,
+    /// The evidence the score is based on
+    pub reasons: Vec<&'static str>,
+}
+
+impl FormatScore {
+    fn new(format: Format) -> Self {
+        Self {
+            format,
+            score: 0,
+            reasons: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, found: bool, points: u32, reason: &'static str) {
+        if found {
+            self.score += points;
+            self.reasons.push(reason);
+        }
+    }
+}
+
+/// How the format of an input was detected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub format: Format,
+    /// Why the format was chosen
+    pub reason: String,
+    /// Every candidate, most likely first
+    pub scores: Vec<FormatScore>,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.format.name(), self.reason)?;
+        for score in &self.scores {
+            write!(f, "\n  {:<5} {:>3}", score.format.name(), score.score)?;
+            if !score.reasons.is_empty() {
+                write!(f, "  {}", score.reasons.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Detect the format of the input, explaining the choice
+pub fn explain_format(input: &str) -> Detection {
+    let trimmed = input.trim();
+    let scores = score_formats(trimmed);
+
+    if trimmed.is_empty() {
+        return Detection {
+            format: Format::Json,
+            reason: "empty input".to_string(),
+            scores,
+        };
+    }
+
+    let candidates: Vec<Format> = scores
+        .iter()
+        .filter(|score| score.score > 0)
+        .map(|score| score.format)
+        .collect();
+    let Some(&likeliest) = candidates.first() else {
+        return Detection {
+            format: Format::Json,
+            reason: "no evidence for any format, defaulting to json".to_string(),
+            scores,
+        };
+    };
+
+    let mut failed = Vec::new();
+    for &format in &candidates {
+        match trial_parse(input, format) {
+            Ok(()) => {
+                let reason = if failed.is_empty() {
+                    "scored highest and parsed".to_string()
+                } else {
+                    format!("{} did not parse", failed.join(", "))
+                };
+                return Detection {
+                    format,
+                    reason,
+                    scores,
+                };
+            }
+            // Report the number rather than read the text as YAML
+            Err(e) if format == Format::Json5 && json5::is_range_error(input, &e) => {
+                return Detection {
+                    format,
+                    reason: "json5 parsed but for a number out of range".to_string(),
+                    scores,
+                };
+            }
+            Err(_) => failed.push(format.name()),
+        }
+    }
+
+    // Report the parse error of the most likely format
+    Detection {
+        format: likeliest,
+        reason: "no candidate parsed, using the highest score".to_string(),
+        scores,
+    }
+}
+
+/// Detect the format from the start of a longer input, explaining the choice
+///
+/// The start may end in the middle of a document, where no format would
+/// parse, so candidates are not trial-parsed: the highest score wins.
+pub fn explain_format_start(start: &str) -> Detection {
+    let scores = score_formats(start.trim());
+    let (format, reason) = match scores.first() {
+        Some(score) if score.score > 0 => {
+            (score.format, "scored highest on the start of the input")
+        }
+        _ => (
+            Format::Json,
+            "no evidence for any format, defaulting to json",
+        ),
+    };
+    Detection {
+        format,
+        reason: reason.to_string(),
+        scores,
+    }
+}
+
+/// Check that the first document of the input parses in the format
+fn trial_parse(input: &str, format: Format) -> Result<(), OqError> {
+    match format {
+        Format::Json | Format::Yaml => deserialize_stream::<IgnoredAny>(input, format)
+            .next()
+            .unwrap_or(Ok(IgnoredAny))
+            .map(drop),
+        _ => deserialize::<IgnoredAny>(input, format).map(drop),
+    }
+}
+
+/// Score every format, most likely first (ties keep JSON, JSON5, YAML, TOML,
+/// TOON, XML, INI, dotenv order)
+fn score_formats(trimmed: &str) -> Vec<FormatScore> {
+    let lines: Vec<&str> = trimmed
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let any = |f: fn(&str) -> bool| lines.iter().any(|l| f(l));
+
+    let mut json = FormatScore::new(Format::Json);
+    json.add(
+        trimmed.starts_with('{') || trimmed.starts_with('['),
+        50,
+        "starts with `{` or `[`",
+    );
+    json.add(
+        trimmed.ends_with('}') || trimmed.ends_with(']'),
+        10,
+        "ends with `}` or `]`",
+    );
+    json.add(trimmed.starts_with('"'), 40, "starts with a string");
+    json.add(
+        matches!(trimmed, "true" | "false" | "null") || trimmed.parse::<f64>().is_ok(),
+        60,
+        "is a literal",
+    );
+
+    // JSON5 scores just below JSON, so it is tried when strict JSON fails
+    let structural = trimmed.starts_with('{') || trimmed.starts_with('[');
+    let mut json5 = FormatScore::new(Format::Json5);
+    json5.add(structural, 45, "starts with `{` or `[`");
+    json5.add(
+        trimmed.ends_with('}') || trimmed.ends_with(']'),
+        10,
+        "ends with `}` or `]`",
+    );
+    json5.add(
+        structural && has_json5_syntax(trimmed),
+        20,
+        "has comments or trailing commas",
+    );
+
+    let mut yaml = FormatScore::new(Format::Yaml);
+    yaml.add(
+        trimmed.starts_with("---") || any(|l| l == "---"),
+        60,
+        "has `---` document markers",
+    );
+    yaml.add(
+        any(|l| l.starts_with("- ") || l == "-"),
+        40,
+        "has `- ` list items",
+    );
+    // YAML is a superset of JSON, so a JSON-like input may be a flow collection
+    yaml.add(
+        trimmed.starts_with('{') || trimmed.starts_with('['),
+        10,
+        "starts with a flow collection",
+    );
+    yaml.add(
+        any(|l| {
+            [": |", ": >", ": |+", ": |-"]
+                .iter()
+                .any(|end| l.ends_with(end))
+        }),
+        40,
+        "has block scalars",
+    );
+    yaml.add(
+        trimmed
+            .lines()
+            .any(|line| line.starts_with("  ") && is_key_value(line.trim())),
+        30,
+        "has nested `key: value` lines",
+    );
+    yaml.add(any(is_key_value), 30, "has `key: value` lines");
+    yaml.add(any(|l| l.starts_with('#')), 5, "has `#` comments");
+
+    let mut toml = FormatScore::new(Format::Toml);
+    toml.add(any(is_table_header), 50, "has `[table]` headers");
+    toml.add(
+        any(|l| l.starts_with("[[") && l.ends_with("]]")),
+        50,
+        "has `[[array]]` tables",
+    );
+    toml.add(any(is_assignment), 40, "has `key = value` lines");
+    toml.add(any(|l| l.starts_with('#')), 5, "has `#` comments");
+
+    let mut toon = FormatScore::new(Format::Toon);
+    toon.add(any(is_array_header), 70, "has `key[N]:` array headers");
+    toon.add(any(is_key_value), 25, "has `key: value` lines");
+
+    let mut xml = FormatScore::new(Format::Xml);
+    xml.add(trimmed.starts_with('<'), 70, "starts with `<`");
+    xml.add(trimmed.ends_with('>'), 10, "ends with `>`");
+
+    // INI shares `[section]` headers and `key = value` with TOML and reads
+    // almost any text, so only what TOML does not allow counts for it: a
+    // broken TOML file reports its error rather than being read as INI
+    let mut ini = FormatScore::new(Format::Ini);
+    ini.add(any(|l| l.starts_with(';')), 60, "has `;` comments");
+    ini.add(
+        any(is_subsection_header),
+        50,
+        "has `[section \"subsection\"]` headers",
+    );
+    // Without sections, such lines are more likely dotenv
+    ini.add(
+        any(is_section_header) && any(is_unquoted_value),
+        60,
+        "has sections with unquoted string values",
+    );
+
+    // Dotenv files have no sections. `KEY=value` scores below TOML, so valid
+    // TOML stays TOML unless a line starts with `export`
+    let mut env = FormatScore::new(Format::Env);
+    env.add(any(|l| l.starts_with("export ")), 60, "has `export` lines");
+    env.add(
+        any(|l| env_name(l).is_some()) && !any(is_section_header),
+        30,
+        "has `KEY=value` lines",
+    );
+
+    let mut scores = vec![json, json5, yaml, toml, toon, xml, ini, env];
+    scores.sort_by_key(|score| std::cmp::Reverse(score.score));
+    scores
+}
+
+/// Whether JSON-like text has comments or trailing commas outside of
+/// strings, which strict JSON rejects
+pub(crate) fn has_json5_syntax(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, &b) in bytes.iter().enumerate() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if b == b'\\' => escaped = true,
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None => match b {
+                b'"' | b'\'' => quote = Some(b),
+                b'/' if matches!(bytes.get(i + 1), Some(b'/' | b'*')) => return true,
+                b',' => {
+                    let next = bytes[i + 1..].iter().find(|b| !b.is_ascii_whitespace());
+                    if matches!(next, Some(b'}' | b']')) {
+                        return true;
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    false
+}
+
+/// Characters of a bare or dotted key
+fn is_key_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// `key: value` or `key:` with an unquoted key
+fn is_key_value(line: &str) -> bool {
+    let key = match line.split_once(": ") {
+        Some((key, _)) => key,
+        None => match line.strip_suffix(':') {
+            Some(key) => key,
+            None => return false,
+        },
+    };
+    !key.is_empty() && key.chars().all(|c| is_key_char(c) || c == ' ')
+}
+
+/// A TOML `[table]` header
+fn is_table_header(line: &str) -> bool {
+    line.strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .is_some_and(|key| !key.is_empty() && key.chars().all(is_key_char))
+}
+
+/// An INI `[section]` or `[section "subsection"]` header
+fn is_section_header(line: &str) -> bool {
+    line.strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .is_some_and(|name| !name.is_empty() && !name.starts_with('['))
+}
+
+/// An INI `[section "subsection"]` header, as in git config
+fn is_subsection_header(line: &str) -> bool {
+    line.strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .and_then(|name| name.split_once(' '))
+        .is_some_and(|(section, subsection)| {
+            let subsection = subsection.trim_start();
+            !section.is_empty()
+                && subsection.len() > 1
+                && subsection.starts_with('"')
+                && subsection.ends_with('"')
+        })
+}
+
+/// A `key = value` line with a string value that TOML would need quoted,
+/// like `ExecStart = /usr/bin/web --port 80` or `After=network.target`
+///
+/// Numbers, booleans and dates are TOML values, and so is anything in
+/// quotes or brackets, even when it is unterminated.
+fn is_unquoted_value(line: &str) -> bool {
+    let Some((key, value)) = line.split_once('=') else {
+        return false;
+    };
+    let value = value.split(" #").next().unwrap_or_default().trim();
+    let literal = value.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '+' | '-'))
+        && !value.contains(char::is_whitespace)
+        || matches!(value, "true" | "false" | "inf" | "nan");
+    !key.trim().is_empty()
+        && !value.is_empty()
+        && !value.starts_with(['"', '\'', '[', '{'])
+        && !literal
+}
+
+/// The name of a dotenv `KEY=value` or `export KEY=value` line
+fn env_name(line: &str) -> Option<&str> {
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (name, _) = line.split_once('=')?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+/// A TOML `key = value` line
+fn is_assignment(line: &str) -> bool {
+    line.split_once('=').is_some_and(|(key, _)| {
+        let key = key.trim_end();
+        !key.is_empty() && key.chars().all(|c| is_key_char(c) || c == '"' || c == '\'')
+    })
+}
+
+/// A TOON array header: `key[N]:`, `key[N]{fields}:` or `[N]: ...`
+fn is_array_header(line: &str) -> bool {
+    let Some((key, rest)) = line.split_once('[') else {
+        return false;
+    };
+    let Some((len, rest)) = rest.split_once(']') else {
+        return false;
+    };
+    let digits = len.trim_end_matches(['\t', '|', ',']);
+    let rest = match rest.strip_prefix('{') {
+        Some(fields) => fields.split_once('}').map_or("", |(_, rest)| rest),
+        None => rest,
+    };
+    key.chars().all(is_key_char)
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && rest.starts_with(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_value_is_yaml() {
+        let detection = explain_format("name: Ada\nage: 30");
+        assert_eq!(detection.format, Format::Yaml);
+        assert_eq!(detection.scores[0].format, Format::Yaml);
+        assert_eq!(detection.scores[1].format, Format::Toon);
+    }
+
+    #[test]
+    fn test_toon_array_headers() {
+        let input = "tags[2]: a,b\nusers[2]{id,name}:\n  1,Ada\n  2,Bob";
+        assert_eq!(explain_format(input).format, Format::Toon);
+    }
+
+    #[test]
+    fn test_bracket_line_is_json_when_it_parses() {
+        assert_eq!(explain_format("[1]").format, Format::Json);
+        assert_eq!(explain_format("[1]\n[2]").format, Format::Json);
+        assert_eq!(explain_format("[server]\nport = 1").format, Format::Toml);
+    }
+
+    #[test]
+    fn test_falls_back_to_candidate_that_parses() {
+        // Looks like JSON but is a YAML flow mapping
+        let detection = explain_format("{name: Ada}");
+        assert_eq!(detection.format, Format::Yaml);
+        assert_eq!(detection.reason, "json, json5 did not parse");
+        // A header-only TOML table is not a JSON array
+        assert_eq!(explain_format("[a]").format, Format::Toml);
+    }
+
+    #[test]
+    fn test_start_is_not_trial_parsed() {
+        // Cut in the middle of a string, the TOML would not parse
+        let start = "[a]\nkey = 1\n[b]\nkey = \"val";
+        assert_eq!(explain_format(start).format, Format::Yaml);
+        assert_eq!(explain_format_start(start).format, Format::Toml);
+    }
+
+    #[test]
+    fn test_markup_is_xml() {
+        let input = "<?xml version=\"1.0\"?>\n<project>\n  <name>oq</name>\n</project>\n";
+        assert_eq!(explain_format(input).format, Format::Xml);
+    }
+
+    #[test]
+    fn test_ini_is_not_toml() {
+        // Unquoted values are not TOML
+        let unit =
+            "[Unit]\nDescription=Web server\n\n[Service]\nExecStart=/usr/bin/web --port 80\n";
+        let detection = explain_format(unit);
+        assert_eq!(detection.format, Format::Ini);
+        assert_eq!(detection.reason, "toml did not parse");
+        assert_eq!(
+            explain_format("[remote \"origin\"]\n\turl = https://x").format,
+            Format::Ini
+        );
+        assert_eq!(
+            explain_format("; php\n[PHP]\nmemory_limit = 128M").format,
+            Format::Ini
+        );
+        // Valid TOML stays TOML
+        assert_eq!(explain_format("[server]\nport = 1").format, Format::Toml);
+    }
+
+    #[test]
+    fn test_broken_toml_is_not_ini() {
+        for input in ["a = [1, 2\n", "name = \"x\n", "port = 80,\nhost = \"x\"\n"] {
+            let detection = explain_format(input);
+            assert_eq!(detection.format, Format::Toml, "{:?}", input);
+            assert_eq!(
+                detection.reason,
+                "no candidate parsed, using the highest score"
+            );
+        }
+    }
+
+    #[test]
+    fn test_json5_after_strict_json() {
+        let input = "{\n  // comment\n  \"a\": [1, 2,],\n}";
+        let detection = explain_format(input);
+        assert_eq!(detection.format, Format::Json5);
+        assert_eq!(detection.scores[0].format, Format::Json5);
+        assert_eq!(explain_format("{a: 1, b: 'x'}").format, Format::Json5);
+        assert_eq!(explain_format("{\"a\": \"//,]\"}").format, Format::Json);
+        assert!(!has_json5_syntax("{\"url\": \"http://x\", \"s\": \",]\"}"));
+        // An integer too large for JSON5 is not YAML
+        assert_eq!(
+            explain_format("{a: 12345678901234567890}").format,
+            Format::Json5
+        );
+    }
+
+    #[test]
+    fn test_dotenv_is_not_ini() {
+        let input = "# app\nDB_HOST=localhost\nDB_PORT=5432\n";
+        assert_eq!(explain_format(input).format, Format::Env);
+        assert_eq!(explain_format("export PATH=/bin").format, Format::Env);
+        // Valid TOML stays TOML without `export`
+        assert_eq!(explain_format("PORT=8080").format, Format::Toml);
+        assert_eq!(explain_format("export PORT=8080").format, Format::Env);
+        // Sections and spaced assignments are INI or TOML
+        assert_eq!(explain_format("[a]\nkey=some value").format, Format::Ini);
+        assert_eq!(explain_format("port = 8080").format, Format::Toml);
+    }
+
+    #[test]
+    fn test_dotted_toml_keys() {
+        assert_eq!(
+            explain_format("server.port = 8080\nserver.host = \"x\"").format,
+            Format::Toml
+        );
+    }
+
+    #[test]
+    fn test_nothing_parses_keeps_likeliest() {
+        let detection = explain_format("{\"a\": [}");
+        assert_eq!(detection.format, Format::Json);
+        assert_eq!(
+            detection.reason,
+            "no candidate parsed, using the highest score"
+        );
+        assert_eq!(explain_format("plain text").format, Format::Json);
+    }
+
+    #[test]
+    fn test_display_lists_scores() {
+        let text = explain_format("a = 1").to_string();
+        assert!(text
+            .starts_with("toml (scored highest and parsed)\n  toml   40  has `key = value` lines"));
+    }
+}
@@ -0,0 +1,609 @@
+//! Parse and compile errors located in their source, rendered with a snippet
+
+use crate::convert::Format;
+use serde_json::{json, Value};
+use std::fmt;
+use std::ops::Range;
+
+/// An input that failed to parse
+///
+/// Renders like a compiler error: the message, the position, and the
+/// offending line with a caret under the error.
+///
+/// ```text
+/// JSON parse error: expected `:`
+///  --> config.json:2:7
+///   |
+/// 2 |   "a" 1}
+///   |       ^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub format: Format,
+    pub message: String,
+    /// Path of the input (`None` for stdin)
+    pub path: Option<String>,
+    /// Where the error is, when the parser reports it
+    pub location: Option<Location>,
+}
+
+/// Position of a parse error in its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    /// Byte range of the error in the source
+    pub span: Range<usize>,
+    /// Text of the line the error starts on
+    pub source_line: String,
+}
+
+impl Location {
+    /// Locate a byte range of the source
+    pub fn from_span(source: &str, span: Range<usize>) -> Self {
+        let mut start = floor_char_boundary(source, span.start);
+        // Point past the last line rather than at an empty one after it
+        if start == source.len() && source.ends_with('\n') {
+            start -= 1;
+        }
+        let end = floor_char_boundary(source, span.end).max(start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        Self {
+            line: source[..start].matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+            span: start..end,
+            source_line: source[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_string(),
+        }
+    }
+
+    /// Locate a 1-based line and byte column of the source
+    fn from_line_column(source: &str, line: usize, column: usize) -> Self {
+        let line_start: usize = source
+            .split_inclusive('\n')
+            .take(line.saturating_sub(1))
+            .map(str::len)
+            .sum();
+        let start = line_start + column.saturating_sub(1);
+        Self::from_span(source, start..start + 1)
+    }
+}
+
+/// The largest char boundary at or before `index`, clamped to the source
+fn floor_char_boundary(source: &str, index: usize) -> usize {
+    let mut index = index.min(source.len());
+    while !source.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+impl Diagnostic {
+    pub fn new(format: Format, message: impl Into<String>) -> Self {
+        Self {
+            format,
+            message: message.into(),
+            path: None,
+            location: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Move the location past `lines` lines and `bytes` bytes of input that
+    /// came before the text the error was found in
+    pub(crate) fn shifted(mut self, lines: usize, bytes: usize) -> Self {
+        if let Some(location) = &mut self.location {
+            location.line += lines;
+            location.span = location.span.start + bytes..location.span.end + bytes;
+        }
+        self
+    }
+
+    pub(crate) fn from_json(source: &str, error: &serde_json::Error) -> Self {
+        let (line, column) = (error.line(), error.column());
+        let message = error.to_string();
+        let suffix = format!(" at line {} column {}", line, column);
+        let diagnostic = Self::new(
+            Format::Json,
+            message.strip_suffix(&suffix).unwrap_or(&message),
+        );
+        if line == 0 {
+            return diagnostic;
+        }
+        diagnostic.with_location(Location::from_line_column(source, line, column))
+    }
+
+    pub(crate) fn from_json5(source: &str, error: &json5::Error) -> Self {
+        let json5::Error::Message { msg, location } = error;
+        // Parser errors are a drawing of the source ending in `= expected ...`
+        let message = msg
+            .lines()
+            .rev()
+            .find_map(|line| line.trim().strip_prefix("= "))
+            .unwrap_or(msg);
+        let diagnostic = Self::new(Format::Json5, message);
+        match location {
+            Some(location) => diagnostic.with_location(Location::from_line_column(
+                source,
+                location.line,
+                location.column,
+            )),
+            None => diagnostic,
+        }
+    }
+
+    pub(crate) fn from_yaml(source: &str, error: &serde_yaml::Error) -> Self {
+        let message = error.to_string();
+        let Some(location) = error.location() else {
+            return Self::new(Format::Yaml, message);
+        };
+        // The position is reported separately
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        let index = location.index();
+        Self::new(Format::Yaml, message.replacen(&suffix, "", 1))
+            .with_location(Location::from_span(source, index..index + 1))
+    }
+
+    pub(crate) fn from_toml(source: &str, error: &toml::de::Error) -> Self {
+        let message = error.message().trim_end().replace('\n', ": ");
+        let diagnostic = Self::new(Format::Toml, message);
+        match error.span() {
+            Some(span) => diagnostic.with_location(Location::from_span(source, span)),
+            None => diagnostic,
+        }
+    }
+
+    pub(crate) fn from_toon(source: &str, error: &toon_format::ToonError) -> Self {
+        use toon_format::ToonError;
+        match error {
+            ToonError::ParseError {
+                line,
+                column,
+                message,
+                ..
+            } => Self::new(Format::Toon, message.as_str())
+                .with_location(Location::from_line_column(source, *line, *column)),
+            ToonError::UnexpectedEof => Self::new(Format::Toon, "unexpected end of input")
+                .with_location(Location::from_span(source, source.len()..source.len())),
+            ToonError::InvalidCharacter { char, position } => {
+                let start = source
+                    .char_indices()
+                    .nth(*position)
+                    .map_or(source.len(), |(i, _)| i);
+                Self::new(Format::Toon, format!("invalid character '{}'", char))
+                    .with_location(Location::from_span(source, start..start + char.len_utf8()))
+            }
+            _ => Self::new(Format::Toon, error.to_string()),
+        }
+    }
+
+    pub(crate) fn from_csv(source: &str, format: Format, error: &csv::Error) -> Self {
+        let diagnostic = Self::new(format, error.to_string());
+        match error.position() {
+            Some(position) => {
+                let start = position.byte() as usize;
+                diagnostic.with_location(Location::from_span(source, start..start + 1))
+            }
+            None => diagnostic,
+        }
+    }
+
+    /// The error as a JSON object, for `--error-format json`
+    pub fn to_json(&self) -> Value {
+        let mut object = json!({
+            "kind": "Parse",
+            "message": self.message,
+            "format": self.format.name(),
+            "file": self.path,
+        });
+        insert_location(&mut object, self.location.as_ref());
+        object
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} parse error: {}",
+            self.format.name().to_uppercase(),
+            self.message
+        )?;
+        let path = self.path.as_deref().unwrap_or("<stdin>");
+        let Some(location) = &self.location else {
+            if let Some(path) = &self.path {
+                write!(f, "\n --> {}", path)?;
+            }
+            return Ok(());
+        };
+
+        write_snippet(f, path, location)
+    }
+}
+
+/// A jq filter or module that failed to load or compile
+///
+/// ```text
+/// compile error: undefined filter `lenght/0`
+///  --> <filter>:1:10
+///   |
+/// 1 | .items | lenght
+///   |          ^^^^^^
+///   = help: did you mean `length`?
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub message: String,
+    /// Path of the file with the error (`None` for a filter given as text)
+    pub path: Option<String>,
+    /// Where the error is in the filter or module text
+    pub location: Option<Location>,
+    /// A close match for an undefined name, e.g. `length` for `lenght`
+    pub suggestion: Option<String>,
+}
+
+impl CompileError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            path: None,
+            location: None,
+            suggestion: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    /// The error as a JSON object, for `--error-format json`
+    ///
+    /// The span is a byte range of the filter (or module) text.
+    pub fn to_json(&self) -> Value {
+        let mut object = json!({
+            "kind": "Compile",
+            "message": self.message,
+            "file": self.path,
+        });
+        insert_location(&mut object, self.location.as_ref());
+        object["suggestion"] = json!(self.suggestion);
+        object
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "compile error: {}", self.message)?;
+        let path = self.path.as_deref().unwrap_or("<filter>");
+        let gutter = match &self.location {
+            Some(location) => {
+                write_snippet(f, path, location)?;
+                " ".repeat(location.line.to_string().len())
+            }
+            None => {
+                if let Some(path) = &self.path {
+                    write!(f, "\n --> {}", path)?;
+                }
+                " ".to_string()
+            }
+        };
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n{gutter} = help: did you mean `{}`?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// A filter that failed while running
+///
+/// Besides jaq's message, it tells where the error happened when oq can
+/// work it out: the path of the offending value in the input, the value
+/// itself, and the part of the filter that failed on it.
+///
+/// ```text
+/// Filter error: cannot use string ("3") as number
+///   input path: .items[37].spec
+///   input value: {"replicas":"3"}
+///   expression: .replicas + 1
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    pub message: String,
+    /// Path of the input file being processed (`None` for stdin)
+    pub file: Option<String>,
+    /// Path of the offending value in the input document, as in jq `path(f)`
+    pub path: Option<Vec<Value>>,
+    /// The offending value as compact JSON, truncated
+    pub value: Option<String>,
+    /// The stage of the filter's pipeline that failed
+    pub expression: Option<String>,
+    /// Where the failing stage is in the filter text
+    pub location: Option<Location>,
+    /// Path of the filter file (`None` for a filter given as text)
+    pub filter_path: Option<String>,
+}
+
+impl FilterError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            file: None,
+            path: None,
+            value: None,
+            expression: None,
+            location: None,
+            filter_path: None,
+        }
+    }
+
+    pub fn with_file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: Vec<Value>) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    pub fn with_value(mut self, value: impl Into<String>) -> Self {
+        self.value = Some(value.into());
+        self
+    }
+
+    pub fn with_expression(mut self, expression: impl Into<String>) -> Self {
+        self.expression = Some(expression.into());
+        self
+    }
+
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn with_filter_path(mut self, path: impl Into<String>) -> Self {
+        self.filter_path = Some(path.into());
+        self
+    }
+
+    /// The path in jq syntax, e.g. `.items[37].spec`
+    pub fn path_string(&self) -> Option<String> {
+        let path = self.path.as_ref()?;
+        if path.is_empty() {
+            return Some(".".to_string());
+        }
+        let mut text = String::new();
+        for part in path {
+            match part {
+                Value::String(key)
+                    if key.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                        && key.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+                {
+                    text.push('.');
+                    text.push_str(key);
+                }
+                Value::Object(slice) => {
+                    let bound = |name| match slice.get(name) {
+                        Some(Value::Null) | None => String::new(),
+                        Some(bound) => bound.to_string(),
+                    };
+                    text.push_str(&format!("[{}:{}]", bound("start"), bound("end")));
+                }
+                part => text.push_str(&format!("[{}]", part)),
+            }
+        }
+        Some(text)
+    }
+
+    /// The error as a JSON object, for `--error-format json`
+    ///
+    /// `expression_location` tells where the failing expression is in the
+    /// filter, with the span as a byte range of the filter text.
+    pub fn to_json(&self) -> Value {
+        let expression_location = self.location.as_ref().map(|location| {
+            let mut object = json!({"file": self.filter_path});
+            insert_location(&mut object, Some(location));
+            object
+        });
+        json!({
+            "kind": "Filter",
+            "message": self.message,
+            "file": self.file,
+            "path": self.path_string(),
+            "value": self.value,
+            "expression": self.expression,
+            "expression_location": expression_location,
+        })
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Filter error: {}", self.message)?;
+        if let Some(file) = &self.file {
+            write!(f, "\n  input file: {}", file)?;
+        }
+        if let Some(path) = self.path_string() {
+            write!(f, "\n  input path: {}", path)?;
+        }
+        if let Some(value) = &self.value {
+            write!(f, "\n  input value: {}", value)?;
+        }
+        if let Some(expression) = &self.expression {
+            write!(f, "\n  expression: {}", expression)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add `line`, `column` and `span` fields, null when the location is unknown
+fn insert_location(object: &mut Value, location: Option<&Location>) {
+    object["line"] = json!(location.map(|location| location.line));
+    object["column"] = json!(location.map(|location| location.column));
+    object["span"] = json!(location
+        .map(|location| { json!({"start": location.span.start, "end": location.span.end}) }));
+}
+
+/// Write the position and the line of an error with a caret under it
+fn write_snippet(f: &mut fmt::Formatter<'_>, path: &str, location: &Location) -> fmt::Result {
+    let line = location.line.to_string();
+    let gutter = " ".repeat(line.len());
+    let before: String = location
+        .source_line
+        .chars()
+        .take(location.column - 1)
+        .collect();
+    // Keep tabs so that the caret lines up with the source
+    let indent: String = before
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let carets = location.source_line[before.len()..]
+        .char_indices()
+        .take_while(|(i, _)| *i < location.span.len())
+        .count()
+        .max(1);
+    write!(
+        f,
+        "\n{gutter}--> {}:{}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}",
+        path,
+        location.line,
+        location.column,
+        line,
+        location.source_line,
+        indent,
+        "^".repeat(carets),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::parse_input;
+    use crate::OqError;
+
+    fn diagnostic(input: &str, format: Format) -> Diagnostic {
+        match parse_input(input, format) {
+            Err(OqError::Parse(diagnostic)) => *diagnostic,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_json_diagnostic() {
+        let diagnostic = diagnostic("{\n  \"a\" 1}", Format::Json).with_path("a.json");
+        assert_eq!(diagnostic.message, "expected `:`");
+        let location = diagnostic.location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (2, 7));
+        assert_eq!(location.span, 8..9);
+        assert_eq!(
+            diagnostic.to_string(),
+            "JSON parse error: expected `:`\n --> a.json:2:7\n  |\n2 |   \"a\" 1}\n  |       ^"
+        );
+    }
+
+    #[test]
+    fn test_yaml_diagnostic() {
+        let diagnostic = diagnostic("a: 1\nb: [1, 2\n", Format::Yaml);
+        assert!(!diagnostic.message.contains("at line 3"));
+        let location = diagnostic.location.unwrap();
+        assert_eq!(location.source_line, "b: [1, 2");
+    }
+
+    #[test]
+    fn test_toml_diagnostic_span() {
+        let diagnostic = diagnostic("a = 1\nb = \"x\" \"y\"\n", Format::Toml);
+        let location = diagnostic.location.unwrap();
+        assert_eq!(location.line, 2);
+        assert_eq!(location.source_line, "b = \"x\" \"y\"");
+        assert!(!diagnostic.message.contains('\n'));
+    }
+
+    #[test]
+    fn test_toon_diagnostic() {
+        let diagnostic = diagnostic("tags[3]: a,b\n", Format::Toon);
+        let location = diagnostic.location.unwrap();
+        assert_eq!(location.line, 1);
+        assert_eq!(location.source_line, "tags[3]: a,b");
+    }
+
+    #[test]
+    fn test_caret_follows_tabs_and_spans() {
+        let location = Location::from_span("\tname = é!", 8..11);
+        assert_eq!(location.column, 9);
+        let text = Diagnostic::new(Format::Toml, "bad")
+            .with_location(location)
+            .to_string();
+        assert!(text.ends_with("1 | \tname = é!\n  | \t       ^^"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let diagnostic = diagnostic("{\n  \"a\" 1}", Format::Json);
+        assert_eq!(
+            diagnostic.to_json(),
+            json!({
+                "kind": "Parse",
+                "message": "expected `:`",
+                "format": "json",
+                "file": null,
+                "line": 2,
+                "column": 7,
+                "span": {"start": 8, "end": 9},
+            })
+        );
+        let error = FilterError::new("boom")
+            .with_path(vec![json!("items"), json!(3)])
+            .with_value("1");
+        assert_eq!(error.to_json()["path"], ".items[3]");
+        assert_eq!(error.to_json()["expression"], Value::Null);
+        let error = error
+            .with_expression(".b")
+            .with_location(Location::from_span(".a | .b", 5..7));
+        assert_eq!(
+            error.to_json()["expression_location"],
+            json!({
+                "file": null,
+                "line": 1,
+                "column": 6,
+                "span": {"start": 5, "end": 7},
+            })
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_without_location() {
+        let diagnostic = Diagnostic::new(Format::Toon, "length mismatch");
+        assert_eq!(diagnostic.to_string(), "TOON parse error: length mismatch");
+        assert_eq!(
+            diagnostic.with_path("a.toon").to_string(),
+            "TOON parse error: length mismatch\n --> a.toon"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Dotenv files and shell variable assignments
+//!
+//! A `.env` file maps to a flat object of strings. Each line is a
+//! `KEY=value` assignment, optionally prefixed with `export`; blank lines
+//! and `#` comments are skipped. Values can be:
+//!
+//! - unquoted, up to a ` #` comment, with surrounding whitespace removed
+//! - in single quotes, taken literally
+//! - in double quotes, with `\n`, `\t`, `\"`, `\\` and `\$` escapes
+//!
+//! Quoted values can span lines. In unquoted and double-quoted values,
+//! `${VAR}` and `$VAR` expand to an earlier key of the file, or else to the
+//! environment variable, and `${VAR:-default}` falls back to `default` when
+//! the variable is unset or empty.
+//!
+//! Encoding writes an object as `KEY=value` lines that a POSIX shell can
+//! `eval`. Nested objects and arrays are flattened by joining the keys (and
+//! indexes) with a separator, and keys are upper-cased with any character
+//! that is not allowed in a variable name replaced by `_`. Two keys that
+//! end up with the same name are an error.
+
+use crate::convert::Format;
+use crate::diagnostic::{Diagnostic, Location};
+use crate::val::key_string;
+use crate::OqError;
+use jaq_json::{Map, Val};
+use std::collections::HashMap;
+
+/// Parse a dotenv file into a flat object of strings
+pub(crate) fn parse(input: &str) -> Result<Val, OqError> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            rest = next;
+            continue;
+        }
+        let assignment = trimmed
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with([' ', '\t']))
+            .map_or(trimmed, str::trim_start);
+        let Some(eq) = assignment.find('=') else {
+            return Err(error_at(input, offset, "expected `KEY=value`"));
+        };
+        let key = assignment[..eq].trim_end();
+        if !is_name(key) {
+            let message = format!("invalid variable name `{}`", key);
+            return Err(error_at(input, offset, &message));
+        }
+
+        // The value starts after `=` and quoted values may run over several
+        // lines, so it is taken from the rest of the input
+        let indent = line.len() - line.trim_start().len();
+        let value_start = indent + (trimmed.len() - assignment.len()) + eq + 1;
+        let value_text = rest[value_start..].trim_start_matches([' ', '\t']);
+        let value_offset = input.len() - value_text.len();
+        let (value, after) = match value_text.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                let Some(end) = closing_quote(&value_text[1..], quote) else {
+                    return Err(error_at(input, value_offset, "unterminated quoted value"));
+                };
+                let inner = &value_text[1..1 + end];
+                let value = match quote {
+                    '\'' => inner.to_string(),
+                    _ => expand(inner, true, &vars),
+                };
+                let after = &value_text[end + 2..];
+                let (tail, next) = after.split_once('\n').unwrap_or((after, ""));
+                let tail = tail.trim();
+                if !tail.is_empty() && !tail.starts_with('#') {
+                    let offset = input.len() - after.len();
+                    return Err(error_at(
+                        input,
+                        offset,
+                        "unexpected text after quoted value",
+                    ));
+                }
+                (value, next)
+            }
+            _ => {
+                let (line, next) = value_text.split_once('\n').unwrap_or((value_text, ""));
+                let value = match line.find(" #").or_else(|| line.find("\t#")) {
+                    Some(pos) => &line[..pos],
+                    None => line,
+                };
+                let value = if value.starts_with('#') { "" } else { value };
+                (expand(value.trim(), false, &vars), next)
+            }
+        };
+
+        match vars.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = value,
+            None => vars.push((key.to_string(), value)),
+        }
+        rest = after;
+    }
+
+    let map: Map = vars
+        .into_iter()
+        .map(|(key, value)| (Val::from(key), Val::from(value)))
+        .collect();
+    Ok(Val::obj(map))
+}
+
+/// Index of the quote that closes a value, skipping `\"` in double quotes
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote == '"' => escaped = true,
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Expand `${VAR}`, `${VAR:-default}` and `$VAR` references, and with
+/// `escapes` the backslash escapes of double quotes
+fn expand(text: &str, escapes: bool, vars: &[(String, String)]) -> String {
+    let lookup = |name: &str| -> Option<String> {
+        vars.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var(name).ok())
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if escapes => match chars.next() {
+                Some((_, 'n')) => out.push('\n'),
+                Some((_, 't')) => out.push('\t'),
+                Some((_, 'r')) => out.push('\r'),
+                Some((_, c)) => out.push(c),
+                None => out.push('\\'),
+            },
+            '$' => {
+                let rest = &text[i + 1..];
+                let (value, len) = match rest.strip_prefix('{').zip(rest.find('}')) {
+                    Some((_, end)) => {
+                        let (name, default) = match rest[1..end].split_once(":-") {
+                            Some((name, default)) => (name, Some(default)),
+                            None => (&rest[1..end], None),
+                        };
+                        let value = lookup(name).filter(|v| !v.is_empty() || default.is_none());
+                        (value.or(default.map(String::from)), end + 1)
+                    }
+                    None => {
+                        let len = rest
+                            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                            .unwrap_or(rest.len());
+                        (lookup(&rest[..len]), len)
+                    }
+                };
+                if len == 0 {
+                    out.push('$');
+                    continue;
+                }
+                out.push_str(&value.unwrap_or_default());
+                for _ in rest[..len].chars() {
+                    chars.next();
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether `name` can be a variable name
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+fn error_at(source: &str, offset: usize, message: &str) -> OqError {
+    Diagnostic::new(Format::Env, message)
+        .with_location(Location::from_span(source, offset..offset + 1))
+        .into()
+}
+
+/// Encode an object as shell variable assignments
+///
+/// Nested keys are joined with `separator`, and with `export` every line
+/// starts with `export `.
+pub(crate) fn encode(value: &Val, separator: &str, export: bool) -> Result<String, OqError> {
+    if !matches!(value, Val::Obj(_)) {
+        return Err(OqError::Encode(Format::Env, "needs an object".to_string()));
+    }
+    let mut vars = Vec::new();
+    flatten(value, String::new(), String::new(), separator, &mut vars);
+    // Different keys can become the same name, and the shell would keep
+    // only the last of them
+    let mut sources = HashMap::new();
+    for (name, source, _) in &vars {
+        if let Some(first) = sources.insert(name, source) {
+            let message = format!("`{}` and `{}` both become `{}`", first, source, name);
+            return Err(OqError::Encode(Format::Env, message));
+        }
+    }
+    let mut out = String::new();
+    for (name, _, value) in vars {
+        if export {
+            out.push_str("export ");
+        }
+        out.push_str(&name);
+        out.push('=');
+        out.push_str(&quote(&value));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Collect the scalars of a value with their flattened variable names and
+/// their paths in jq syntax (`.db.ports[0]`)
+fn flatten(
+    value: &Val,
+    prefix: String,
+    path: String,
+    separator: &str,
+    vars: &mut Vec<(String, String, String)>,
+) {
+    let join = |key: &str| match prefix.is_empty() {
+        true => key.to_string(),
+        false => format!("{}{}{}", prefix, separator, key),
+    };
+    match value {
+        Val::Obj(obj) => {
+            for (key, value) in obj.iter() {
+                let key = key_string(key);
+                let is_identifier = key.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    && key.chars().all(|c| c.is_alphanumeric() || c == '_');
+                let path = match is_identifier {
+                    true => format!("{}.{}", path, key),
+                    false => format!("{}.{:?}", path, key),
+                };
+                flatten(value, join(&key), path, separator, vars);
+            }
+        }
+        Val::Arr(values) => {
+            for (i, value) in values.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                flatten(value, join(&i.to_string()), path, separator, vars);
+            }
+        }
+        Val::Null => vars.push((variable_name(&prefix), path, String::new())),
+        value => vars.push((variable_name(&prefix), path, key_string(value))),
+    }
+}
+
+/// A path of keys as an upper-case variable name
+fn variable_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    match name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        true => format!("_{}", name),
+        false => name,
+    }
+}
+
+/// A value as a shell word: as-is if it only has safe characters, otherwise
+/// in single quotes, or double quotes if it has a single quote
+fn quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-.,:/@%+=".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        value.to_string()
+    } else if !value.contains('\'') {
+        format!("'{}'", value)
+    } else {
+        let mut out = String::with_capacity(value.len() + 2);
+        out.push('"');
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | '$' | '`') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out.push('"');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::val::{json_to_val, val_to_json};
+    use serde_json::json;
+
+    fn parse_json(input: &str) -> serde_json::Value {
+        val_to_json(&parse(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let input = r#"# database
+export DB_HOST=localhost
+DB_PORT = 5432   # default port
+DB_URL="postgres://${DB_HOST}:$DB_PORT/app"
+GREETING='Hello, $USER # not a comment'
+MULTI="line one
+line two\t\$HOME"
+FALLBACK=${UNSET_OQ_TEST_VAR:-none}
+EMPTY=
+"#;
+        assert_eq!(
+            parse_json(input),
+            json!({
+                "DB_HOST": "localhost",
+                "DB_PORT": "5432",
+                "DB_URL": "postgres://localhost:5432/app",
+                "GREETING": "Hello, $USER # not a comment",
+                "MULTI": "line one\nline two\t$HOME",
+                "FALLBACK": "none",
+                "EMPTY": "",
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for input in ["JUST_A_WORD", "1KEY=x", "KEY=\"open"] {
+            assert!(matches!(parse(input), Err(OqError::Parse(_))), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_encode_flattens_and_quotes() {
+        let value = json_to_val(json!({
+            "db": {"host": "localhost", "password": "it's $secret"},
+            "hosts": ["a", "b"],
+            "debug-mode": true,
+            "empty": null,
+        }));
+        assert_eq!(
+            encode(&value, "__", false).unwrap(),
+            "DB__HOST=localhost\nDB__PASSWORD=\"it's \\$secret\"\nHOSTS__0=a\nHOSTS__1=b\nDEBUG_MODE=true\nEMPTY=''\n"
+        );
+        let value = json_to_val(json!({"port": 80}));
+        assert_eq!(encode(&value, "_", true).unwrap(), "export PORT=80\n");
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let value = json_to_val(json!({"A": "x y", "B": "it's", "C": "$HOME"}));
+        let text = encode(&value, "__", false).unwrap();
+        assert_eq!(parse(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encode_rejects_name_collisions() {
+        let error = |value, separator| match encode(&json_to_val(value), separator, false) {
+            Err(OqError::Encode(Format::Env, message)) => message,
+            other => panic!("expected an encode error, got {:?}", other),
+        };
+        assert_eq!(
+            error(json!({"a-b": 1, "a_b": 2}), "__"),
+            "`.\"a-b\"` and `.a_b` both become `A_B`"
+        );
+        assert_eq!(
+            error(json!({"x": {"y": 1}, "x_y": 3}), "_"),
+            "`.x.y` and `.x_y` both become `X_Y`"
+        );
+    }
+}
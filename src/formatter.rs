@@ -20,7 +20,7 @@
 //! }
 //! ```
 
-use crate::OqError;
+use crate::{Format, OqError};
 use serde_json::Value;
 use std::collections::HashMap;
 
@@ -172,7 +172,7 @@ impl ContentFormatter {
         // Render metadata as TOON
         if !metadata.is_empty() {
             let toon = toon_format::encode_default(&Value::Object(metadata))
-                .map_err(|e| OqError::ToonParse(e.to_string()))?;
+                .map_err(|e| OqError::Encode(Format::Toon, e.to_string()))?;
             output.push_str(&toon);
         }
 
@@ -215,7 +215,7 @@ impl ContentFormatter {
     }
 
     fn format_simple(&self, value: &Value) -> Result<String, OqError> {
-        toon_format::encode_default(value).map_err(|e| OqError::ToonParse(e.to_string()))
+        toon_format::encode_default(value).map_err(|e| OqError::Encode(Format::Toon, e.to_string()))
     }
 
     fn detect_language(&self, map: &serde_json::Map<String, Value>) -> Option<String> {
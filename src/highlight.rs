@@ -0,0 +1,682 @@
+//! Syntax highlighting for encoded output
+//!
+//! Highlighting works on the text produced by the encoders, so every output
+//! format gets colors without a separate writer. Colors follow jq: each kind
+//! of token gets an SGR sequence, configurable through `OQ_COLORS` with the
+//! same colon-separated layout as `JQ_COLORS`:
+//!
+//! ```text
+//! null:false:true:numbers:strings:arrays:objects:objkeys
+//! ```
+//!
+//! For example `OQ_COLORS="1;30:0;31:0;32"` makes `null` bold black, `false`
+//! red and `true` green, keeping the default colors for the rest.
+
+use crate::convert::Format;
+
+/// Kinds of tokens that can be colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Null,
+    False,
+    True,
+    Number,
+    String,
+    /// Array punctuation (`[`, `]`, `,`, list markers)
+    Array,
+    /// Object punctuation (`{`, `}`, `:`, table headers)
+    Object,
+    /// Object keys
+    Key,
+}
+
+impl Token {
+    /// Position of the token in an `OQ_COLORS` specification
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// SGR color sequences for each token kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorScheme {
+    colors: [String; 8],
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        // Same defaults as jq 1.7.1
+        let colors = [
+            "0;90", "0;39", "0;39", "0;39", "0;32", "1;39", "1;39", "34;1",
+        ];
+        Self {
+            colors: colors.map(String::from),
+        }
+    }
+}
+
+impl ColorScheme {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a `JQ_COLORS`-style specification on top of the defaults
+    ///
+    /// Returns `None` if any entry is not a valid SGR parameter list.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut scheme = Self::default();
+        for (i, sgr) in spec.split(':').enumerate() {
+            let valid = sgr.chars().all(|c| c.is_ascii_digit() || c == ';');
+            if i >= scheme.colors.len() || !valid {
+                return None;
+            }
+            scheme.colors[i] = sgr.to_string();
+        }
+        Some(scheme)
+    }
+
+    /// Colors from the `OQ_COLORS` environment variable, or the defaults
+    ///
+    /// Returns an error message if the variable is set but invalid.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("OQ_COLORS") {
+            Ok(spec) => Self::parse(&spec).ok_or_else(|| format!("invalid OQ_COLORS: {}", spec)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Set the SGR sequence for a token kind (e.g. `"1;31"`)
+    pub fn with_color(mut self, token: Token, sgr: &str) -> Self {
+        self.colors[token.index()] = sgr.to_string();
+        self
+    }
+
+    /// Append `text` to `out`, wrapped in the color for `token`
+    fn paint(&self, out: &mut String, token: Token, text: &str) {
+        let sgr = &self.colors[token.index()];
+        if sgr.is_empty() || text.is_empty() {
+            out.push_str(text);
+        } else {
+            out.push_str("\x1b[");
+            out.push_str(sgr);
+            out.push('m');
+            out.push_str(text);
+            out.push_str("\x1b[0m");
+        }
+    }
+}
+
+/// Highlight text that was encoded in the given format
+pub fn highlight(text: &str, format: Format, scheme: &ColorScheme) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    match format {
+        Format::Json | Format::Json5 => highlight_inline(text, scheme, &mut out),
+        Format::Yaml => highlight_yaml(text, scheme, &mut out),
+        // INI output is laid out like TOML
+        Format::Toml | Format::Ini => highlight_toml(text, scheme, &mut out),
+        Format::Toon => highlight_toon(text, scheme, &mut out),
+        // Fields are plain text
+        Format::Csv | Format::Tsv => out.push_str(text),
+        Format::Xml => highlight_xml(text, scheme, &mut out),
+        Format::Env => highlight_env(text, scheme, &mut out),
+    }
+    out
+}
+
+/// Highlight JSON-like text: quoted strings, numbers, literals and brackets
+///
+/// This is used for JSON and JSON5 documents and for TOML values (inline
+/// arrays and tables). Strings directly followed by `:`, bare words followed
+/// by `=` and identifiers followed by `:` (JSON5 keys) are keys.
+fn highlight_inline(text: &str, scheme: &ColorScheme, out: &mut String) {
+    let mut containers = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let container = containers.last().copied().unwrap_or(Token::Array);
+        let len = match c {
+            '"' | '\'' => {
+                let len = quoted_len(rest);
+                let after = rest[len..].trim_start_matches([' ', '\t']);
+                let token = if after.starts_with(':') || after.starts_with('=') {
+                    Token::Key
+                } else {
+                    Token::String
+                };
+                scheme.paint(out, token, &rest[..len]);
+                len
+            }
+            '[' | '{' => {
+                let token = if c == '[' {
+                    Token::Array
+                } else {
+                    Token::Object
+                };
+                containers.push(token);
+                scheme.paint(out, token, &rest[..1]);
+                1
+            }
+            ']' | '}' => {
+                containers.pop();
+                let token = if c == ']' {
+                    Token::Array
+                } else {
+                    Token::Object
+                };
+                scheme.paint(out, token, &rest[..1]);
+                1
+            }
+            ',' | ':' => {
+                scheme.paint(out, container, &rest[..1]);
+                1
+            }
+            c if c.is_whitespace() || c == '=' => {
+                out.push(c);
+                c.len_utf8()
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || ",:=[]{}\"".contains(c))
+                    .unwrap_or(rest.len());
+                let len = len.max(c.len_utf8());
+                let word = &rest[..len];
+                let after = rest[len..].trim_start_matches([' ', '\t']);
+                let identifier =
+                    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$');
+                if after.starts_with('=') || (identifier && after.starts_with(':')) {
+                    scheme.paint(out, Token::Key, word);
+                } else {
+                    scheme.paint(out, scalar_token(word), word);
+                }
+                len
+            }
+        };
+        rest = &rest[len..];
+    }
+}
+
+/// Highlight YAML block-style output
+fn highlight_yaml(text: &str, scheme: &ColorScheme, out: &mut String) {
+    // Indentation of the line that opened a block scalar (`key: |`)
+    let mut block_indent: Option<usize> = None;
+
+    for line in text.split_inclusive('\n') {
+        let (content, newline) = split_newline(line);
+        let indent = content.len() - content.trim_start().len();
+
+        if let Some(block) = block_indent {
+            if content.trim().is_empty() || indent > block {
+                scheme.paint(out, Token::String, content);
+                out.push_str(newline);
+                continue;
+            }
+            block_indent = None;
+        }
+
+        out.push_str(&content[..indent]);
+        let mut rest = &content[indent..];
+
+        if rest == "---" || rest == "..." || rest.starts_with('#') {
+            out.push_str(rest);
+            out.push_str(newline);
+            continue;
+        }
+
+        // Sequence item markers, possibly nested (`- - a`)
+        while rest == "-" || rest.starts_with("- ") {
+            scheme.paint(out, Token::Array, "-");
+            let after = rest[1..].trim_start_matches(' ');
+            out.push_str(&rest[1..rest.len() - after.len()]);
+            rest = after;
+        }
+
+        let value = match split_key(rest) {
+            Some((key, sep, value)) => {
+                scheme.paint(out, Token::Key, key);
+                scheme.paint(out, Token::Object, ":");
+                out.push_str(&sep[1..]);
+                value
+            }
+            None => rest,
+        };
+
+        if value.starts_with('|') || value.starts_with('>') {
+            block_indent = Some(indent);
+            scheme.paint(out, Token::String, value);
+        } else if value == "[]" {
+            scheme.paint(out, Token::Array, value);
+        } else if value == "{}" {
+            scheme.paint(out, Token::Object, value);
+        } else {
+            scheme.paint(out, scalar_token(value), value);
+        }
+        out.push_str(newline);
+    }
+}
+
+/// Highlight TOML output
+fn highlight_toml(text: &str, scheme: &ColorScheme, out: &mut String) {
+    // Delimiter of an open multi-line string (`"""` or `'''`)
+    let mut multiline: Option<&str> = None;
+
+    for line in text.split_inclusive('\n') {
+        let (content, newline) = split_newline(line);
+
+        if let Some(delim) = multiline {
+            scheme.paint(out, Token::String, content);
+            out.push_str(newline);
+            if content.contains(delim) {
+                multiline = None;
+            }
+            continue;
+        }
+
+        let trimmed = content.trim_start();
+        if trimmed.starts_with('[') && !trimmed.contains('=') && !trimmed.ends_with(',') {
+            // Table header: `[name]` or `[[name]]`
+            let indent = &content[..content.len() - trimmed.len()];
+            let name_start = trimmed.len() - trimmed.trim_start_matches('[').len();
+            let name_end = trimmed.trim_end_matches(']').len();
+            out.push_str(indent);
+            if name_start <= name_end {
+                scheme.paint(out, Token::Object, &trimmed[..name_start]);
+                scheme.paint(out, Token::Key, &trimmed[name_start..name_end]);
+                scheme.paint(out, Token::Object, &trimmed[name_end..]);
+            } else {
+                scheme.paint(out, Token::Object, trimmed);
+            }
+        } else {
+            if let Some(pos) = content.find("= \"\"\"").or_else(|| content.find("= '''")) {
+                let delim = &content[pos + 2..pos + 5];
+                if !content[pos + 5..].contains(delim) {
+                    multiline = Some(delim);
+                    highlight_inline(&content[..pos + 2], scheme, out);
+                    scheme.paint(out, Token::String, &content[pos + 2..]);
+                    out.push_str(newline);
+                    continue;
+                }
+            }
+            highlight_inline(content, scheme, out);
+        }
+        out.push_str(newline);
+    }
+}
+
+/// Highlight TOON output
+fn highlight_toon(text: &str, scheme: &ColorScheme, out: &mut String) {
+    for line in text.split_inclusive('\n') {
+        let (content, newline) = split_newline(line);
+        let indent = content.len() - content.trim_start().len();
+        out.push_str(&content[..indent]);
+        let mut rest = &content[indent..];
+
+        if rest == "-" || rest.starts_with("- ") {
+            scheme.paint(out, Token::Array, "-");
+            let after = rest[1..].trim_start_matches(' ');
+            out.push_str(&rest[1..rest.len() - after.len()]);
+            rest = after;
+        }
+
+        match split_key(rest) {
+            Some((key, sep, value)) => {
+                // `key[N]{fields}:` headers color the length and field list
+                let (name, header) = match key.find('[') {
+                    Some(pos) if !key.starts_with('"') => key.split_at(pos),
+                    _ => (key, ""),
+                };
+                scheme.paint(out, Token::Key, name);
+                highlight_toon_header(header, scheme, out);
+                scheme.paint(out, Token::Object, ":");
+                out.push_str(&sep[1..]);
+                if header.is_empty() {
+                    scheme.paint(out, scalar_token(value), value);
+                } else {
+                    highlight_toon_row(value, scheme, out);
+                }
+            }
+            // Rows of a tabular array, or bare list items
+            None => highlight_toon_row(rest, scheme, out),
+        }
+        out.push_str(newline);
+    }
+}
+
+/// Highlight a TOON array header such as `[2]{id,name}`
+fn highlight_toon_header(header: &str, scheme: &ColorScheme, out: &mut String) {
+    match header.find('{') {
+        Some(pos) => {
+            scheme.paint(out, Token::Array, &header[..pos]);
+            scheme.paint(out, Token::Object, "{");
+            let fields = header[pos + 1..].trim_end_matches('}');
+            for (i, field) in fields.split(',').enumerate() {
+                if i > 0 {
+                    scheme.paint(out, Token::Object, ",");
+                }
+                scheme.paint(out, Token::Key, field);
+            }
+            scheme.paint(out, Token::Object, &header[pos + 1 + fields.len()..]);
+        }
+        None => scheme.paint(out, Token::Array, header),
+    }
+}
+
+/// Highlight delimiter-separated TOON values
+fn highlight_toon_row(row: &str, scheme: &ColorScheme, out: &mut String) {
+    let mut rest = row;
+    loop {
+        let len = value_len(rest, |c| c == ',' || c == '|' || c == '\t');
+        let value = &rest[..len];
+        scheme.paint(out, scalar_token(value), value);
+        match rest[len..].chars().next() {
+            Some(delim) => {
+                scheme.paint(out, Token::Array, &rest[len..len + delim.len_utf8()]);
+                rest = &rest[len + delim.len_utf8()..];
+            }
+            None => break,
+        }
+    }
+}
+
+/// Highlight XML output: element and attribute names as keys, attribute
+/// values and text as strings
+fn highlight_xml(text: &str, scheme: &ColorScheme, out: &mut String) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let len = if rest.starts_with("<?") || rest.starts_with("<!") {
+            // Declarations and comments stay plain
+            let len = rest.find('>').map_or(rest.len(), |i| i + 1);
+            out.push_str(&rest[..len]);
+            len
+        } else if rest.starts_with('<') {
+            let len = rest.find('>').map_or(rest.len(), |i| i + 1);
+            highlight_xml_tag(&rest[..len], scheme, out);
+            len
+        } else {
+            let len = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..len];
+            let start = text.len() - text.trim_start().len();
+            let content = text.trim();
+            out.push_str(&text[..start]);
+            scheme.paint(out, Token::String, content);
+            out.push_str(&text[start + content.len()..]);
+            len
+        };
+        rest = &rest[len..];
+    }
+}
+
+/// Highlight a start, end or empty-element tag
+fn highlight_xml_tag(tag: &str, scheme: &ColorScheme, out: &mut String) {
+    let open = if tag.starts_with("</") { 2 } else { 1 };
+    let close = if tag.ends_with("/>") {
+        2
+    } else {
+        usize::from(tag.ends_with('>'))
+    };
+    let close = close.min(tag.len() - open);
+    scheme.paint(out, Token::Object, &tag[..open]);
+
+    let mut rest = &tag[open..tag.len() - close];
+    let name_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    scheme.paint(out, Token::Key, &rest[..name_len]);
+    rest = &rest[name_len..];
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '"' | '\'' => {
+                let len = rest[1..].find(c).map_or(rest.len(), |i| i + 2);
+                scheme.paint(out, Token::String, &rest[..len]);
+                len
+            }
+            '=' => {
+                scheme.paint(out, Token::Object, "=");
+                1
+            }
+            c if c.is_whitespace() => {
+                out.push(c);
+                c.len_utf8()
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c == '=' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                scheme.paint(out, Token::Key, &rest[..len]);
+                len
+            }
+        };
+        rest = &rest[len..];
+    }
+    scheme.paint(out, Token::Object, &tag[tag.len() - close..]);
+}
+
+/// Highlight env output: `[export ]KEY=value` lines, where quoted values
+/// can span lines
+fn highlight_env(text: &str, scheme: &ColorScheme, out: &mut String) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("export ") {
+            out.push_str("export ");
+            rest = after;
+        }
+        let Some(eq) = rest.find('=').filter(|&eq| !rest[..eq].contains('\n')) else {
+            out.push_str(rest);
+            break;
+        };
+        scheme.paint(out, Token::Key, &rest[..eq]);
+        scheme.paint(out, Token::Object, "=");
+        rest = &rest[eq + 1..];
+
+        let len = match rest.chars().next() {
+            Some('\'') => rest[1..].find('\'').map_or(rest.len(), |i| i + 2),
+            Some('"') => {
+                let mut escaped = false;
+                rest.char_indices()
+                    .skip(1)
+                    .find(|&(_, c)| match c {
+                        _ if escaped => {
+                            escaped = false;
+                            false
+                        }
+                        '\\' => {
+                            escaped = true;
+                            false
+                        }
+                        c => c == '"',
+                    })
+                    .map_or(rest.len(), |(i, _)| i + 1)
+            }
+            _ => rest.find('\n').unwrap_or(rest.len()),
+        };
+        scheme.paint(out, Token::String, &rest[..len]);
+        rest = &rest[len..];
+        let newline = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        out.push_str(&rest[..newline]);
+        rest = &rest[newline..];
+    }
+}
+
+/// Classify a scalar written without surrounding structure
+fn scalar_token(value: &str) -> Token {
+    match value {
+        "null" | "~" => Token::Null,
+        "true" => Token::True,
+        "false" => Token::False,
+        v if is_number(v) => Token::Number,
+        _ => Token::String,
+    }
+}
+
+fn is_number(value: &str) -> bool {
+    let digits = value.trim_start_matches(['-', '+']);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        && value.parse::<f64>().is_ok()
+        && !value.ends_with('.')
+}
+
+/// Split `key: value` at the first `:` outside quotes that ends the line or
+/// is followed by a space
+///
+/// Returns the key, the separator (`:` plus following spaces) and the value.
+fn split_key(line: &str) -> Option<(&str, &str, &str)> {
+    let len = value_len(line, |c| c == ':');
+    let after = &line[len..];
+    if !after.starts_with(':') {
+        return None;
+    }
+    let value = after[1..].trim_start_matches(' ');
+    if value.len() == after.len() - 1 && !value.is_empty() {
+        // `a:b` is a plain scalar, not a key
+        return None;
+    }
+    Some((&line[..len], &after[..after.len() - value.len()], value))
+}
+
+/// Length of the text before the first unquoted character matching `stop`
+fn value_len(text: &str, stop: impl Fn(char) -> bool) -> usize {
+    let mut pos = 0;
+    while let Some(c) = text[pos..].chars().next() {
+        if c == '"' || (c == '\'' && pos == 0) {
+            pos += quoted_len(&text[pos..]);
+        } else if stop(c) {
+            break;
+        } else {
+            pos += c.len_utf8();
+        }
+    }
+    pos
+}
+
+/// Length of a quoted string at the start of `text`, including the quotes
+fn quoted_len(text: &str) -> usize {
+    let quote = text.chars().next().unwrap_or('"');
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return i + 1,
+            _ => escaped = false,
+        }
+    }
+    text.len()
+}
+
+fn split_newline(line: &str) -> (&str, &str) {
+    let content = line.trim_end_matches(['\n', '\r']);
+    (content, &line[content.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Remove SGR escapes, leaving the original text
+    fn strip(text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(pos) = rest.find('\x1b') {
+            out.push_str(&rest[..pos]);
+            let end = rest[pos..].find('m').unwrap();
+            rest = &rest[pos + end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn colored(token_sgr: &str, text: &str) -> String {
+        format!("\x1b[{}m{}\x1b[0m", token_sgr, text)
+    }
+
+    #[test]
+    fn test_parse_scheme() {
+        let scheme = ColorScheme::parse("1;30:0;31").unwrap();
+        assert_eq!(scheme.colors[0], "1;30");
+        assert_eq!(scheme.colors[1], "0;31");
+        assert_eq!(scheme.colors[4], "0;32");
+        assert!(ColorScheme::parse("red").is_none());
+        assert!(ColorScheme::parse("1:2:3:4:5:6:7:8:9").is_none());
+    }
+
+    #[test]
+    fn test_highlight_json() {
+        let scheme = ColorScheme::default();
+        let text = "{\n  \"name\": \"Ada\",\n  \"age\": 36,\n  \"ok\": true,\n  \"x\": null\n}";
+        let out = highlight(text, Format::Json, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.contains(&colored("34;1", "\"name\"")));
+        assert!(out.contains(&colored("0;32", "\"Ada\"")));
+        assert!(out.contains(&colored("0;39", "36")));
+        assert!(out.contains(&colored("0;90", "null")));
+    }
+
+    #[test]
+    fn test_highlight_yaml() {
+        let scheme = ColorScheme::default().with_color(Token::Number, "33");
+        let text = "name: Ada\nlangs:\n- en\nbio: |-\n  line one\n  line: two\nage: 36\n";
+        let out = highlight(text, Format::Yaml, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.contains(&colored("34;1", "name")));
+        assert!(out.contains(&colored("0;32", "  line: two")));
+        assert!(out.contains(&colored("33", "36")));
+    }
+
+    #[test]
+    fn test_highlight_toml() {
+        let scheme = ColorScheme::default();
+        let text = "title = \"x\"\ntags = [\"a\", 1]\n\n[owner]\nname = \"Ada\"\n";
+        let out = highlight(text, Format::Toml, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.contains(&colored("34;1", "title")));
+        assert!(out.contains(&colored("34;1", "owner")));
+        assert!(out.contains(&colored("0;32", "\"Ada\"")));
+    }
+
+    #[test]
+    fn test_highlight_toon() {
+        let scheme = ColorScheme::default();
+        let text = "users[2]{id,name}:\n  1,Ada\n  2,Bob\ntags[2]: a,b\nok: true";
+        let out = highlight(text, Format::Toon, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.contains(&colored("34;1", "users")));
+        assert!(out.contains(&colored("34;1", "name")));
+        assert!(out.contains(&colored("0;32", "Ada")));
+        assert!(out.contains(&colored("0;39", "true")));
+    }
+
+    #[test]
+    fn test_highlight_xml() {
+        let scheme = ColorScheme::default();
+        let text = "<?xml version=\"1.0\"?>\n<a id=\"1\">\n  <b>x y</b>\n  <c/>\n</a>";
+        let out = highlight(text, Format::Xml, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.starts_with("<?xml version=\"1.0\"?>\n"));
+        assert!(out.contains(&colored("34;1", "id")));
+        assert!(out.contains(&colored("0;32", "\"1\"")));
+        assert!(out.contains(&colored("0;32", "x y")));
+        assert!(out.contains(&format!(
+            "{}{}",
+            colored("1;39", "</"),
+            colored("34;1", "a")
+        )));
+    }
+
+    #[test]
+    fn test_highlight_json5_keys() {
+        let scheme = ColorScheme::default();
+        let text = "{\n  name: \"oq\",\n  \"two words\": true\n}";
+        let out = highlight(text, Format::Json5, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.contains(&colored("34;1", "name")));
+        assert!(out.contains(&colored("34;1", "\"two words\"")));
+        assert!(out.contains(&colored("0;32", "\"oq\"")));
+    }
+
+    #[test]
+    fn test_highlight_env() {
+        let scheme = ColorScheme::default();
+        let text = "export HOST=localhost\nNOTE='two\nlines'\nPASSWORD=\"it's \\\"x\\\"\"\n";
+        let out = highlight(text, Format::Env, &scheme);
+        assert_eq!(strip(&out), text);
+        assert!(out.starts_with(&format!("export {}", colored("34;1", "HOST"))));
+        assert!(out.contains(&colored("0;32", "'two\nlines'")));
+        assert!(out.contains(&colored("0;32", "\"it's \\\"x\\\"\"")));
+    }
+}
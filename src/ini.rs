@@ -0,0 +1,439 @@
+//! INI files: git config, `.editorconfig`, systemd units, PHP ini
+//!
+//! An INI file maps to an object with a key per section, holding an object
+//! of the section's keys. Keys before the first section are top-level keys.
+//! Git-style subsections (`[remote "origin"]`) are nested under their
+//! section, and sections that appear more than once are merged.
+//!
+//! Values are strings unless types are inferred. Quotes around a value are
+//! removed, a line ending in `\` continues on the next line and a key
+//! without a value (`bare`) is `true`. A key that appears more than once in
+//! a section, or that ends in `[]` (`ext[] = a`), is an array of its values.
+//! Lines starting with `;` or `#` are comments, and so is the rest of a line
+//! after ` ;` or ` #` outside of quotes.
+
+use crate::convert::{Format, InputOptions};
+use crate::delimited::infer;
+use crate::diagnostic::{Diagnostic, Location};
+use crate::val::key_string;
+use crate::OqError;
+use jaq_json::{Map, Val};
+
+/// Keys and values of a section, in order, with the values of repeated
+/// keys collected
+#[derive(Default)]
+struct Section {
+    entries: Vec<(String, Vec<Val>, bool)>,
+}
+
+impl Section {
+    fn push(&mut self, key: &str, value: Val) {
+        let (key, array) = match key.strip_suffix("[]") {
+            Some(key) => (key.trim_end(), true),
+            None => (key, false),
+        };
+        match self.entries.iter_mut().find(|(k, _, _)| k == key) {
+            Some((_, values, is_array)) => {
+                values.push(value);
+                *is_array |= array;
+            }
+            None => self.entries.push((key.to_string(), vec![value], array)),
+        }
+    }
+
+    fn into_map(self) -> Map {
+        self.entries
+            .into_iter()
+            .map(|(key, mut values, array)| {
+                let value = match values.len() {
+                    1 if !array => values.remove(0),
+                    _ => Val::from_iter(values),
+                };
+                (Val::from(key), value)
+            })
+            .collect()
+    }
+}
+
+/// Parse an INI file into an object of sections
+pub(crate) fn parse(input: &str, options: &InputOptions) -> Result<Val, OqError> {
+    // Sections by their (section, subsection) names, in order of appearance
+    let mut sections: Vec<((String, Option<String>), Section)> = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut globals = Section::default();
+
+    let mut lines = input.split_inclusive('\n').peekable();
+    let mut offset = 0;
+    while let Some(line) = lines.next() {
+        let start = offset;
+        offset += line.len();
+        let mut text = line.trim().to_string();
+        if text.is_empty() || text.starts_with(';') || text.starts_with('#') {
+            continue;
+        }
+        // Continuation lines
+        while text.ends_with('\\') && lines.peek().is_some() {
+            text.pop();
+            if let Some(next) = lines.next() {
+                offset += next.len();
+                text.push_str(next.trim());
+            }
+        }
+
+        if let Some(header) = text.strip_prefix('[') {
+            let Some(name) = strip_comment(header).strip_suffix(']') else {
+                return Err(error_at(
+                    input,
+                    start,
+                    "section header is not closed with `]`",
+                ));
+            };
+            let name = section_name(name.trim());
+            let index = match sections.iter().position(|(n, _)| *n == name) {
+                Some(index) => index,
+                None => {
+                    sections.push((name, Section::default()));
+                    sections.len() - 1
+                }
+            };
+            current = Some(index);
+            continue;
+        }
+
+        let (key, value) = match text.find(['=', ':']) {
+            Some(pos) => (text[..pos].trim(), Some(text[pos + 1..].trim())),
+            None => (strip_comment(&text).trim(), None),
+        };
+        if key.is_empty() {
+            return Err(error_at(input, start, "expected a key before `=`"));
+        }
+        let value = match value {
+            None => Val::Bool(true),
+            Some(value) => {
+                let value = unquote(strip_comment(value).trim());
+                if options.infer_types {
+                    infer(&value)
+                } else {
+                    Val::from(value)
+                }
+            }
+        };
+        match current {
+            Some(index) => sections[index].1.push(key, value),
+            None => globals.push(key, value),
+        }
+    }
+
+    // Subsections go into the object of their section
+    let mut tables: Vec<(String, Map)> = Vec::new();
+    for ((name, subsection), section) in sections {
+        let index = match tables.iter().position(|(n, _)| *n == name) {
+            Some(index) => index,
+            None => {
+                tables.push((name, Map::default()));
+                tables.len() - 1
+            }
+        };
+        let table = &mut tables[index].1;
+        match subsection {
+            Some(subsection) => {
+                table.insert(Val::from(subsection), Val::obj(section.into_map()));
+            }
+            None => table.extend(section.into_map()),
+        }
+    }
+    let mut map = globals.into_map();
+    for (name, table) in tables {
+        map.insert(Val::from(name), Val::obj(table));
+    }
+    Ok(Val::obj(map))
+}
+
+/// Split a `section "subsection"` header name
+fn section_name(name: &str) -> (String, Option<String>) {
+    match name.split_once(char::is_whitespace) {
+        Some((section, sub)) if sub.trim().starts_with('"') => {
+            (section.to_string(), Some(unquote(sub.trim())))
+        }
+        _ => (name.to_string(), None),
+    }
+}
+
+/// Text before a ` ;` or ` #` comment outside of quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q && previous != '\\' => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if (c == ';' || c == '#') && previous.is_whitespace() && i > 0 => {
+                return &text[..i];
+            }
+            None => {}
+        }
+        previous = c;
+    }
+    text
+}
+
+/// A value without its quotes, with escapes in double quotes resolved
+fn unquote(value: &str) -> String {
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return inner.to_string();
+    }
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn error_at(source: &str, offset: usize, message: &str) -> OqError {
+    Diagnostic::new(Format::Ini, message)
+        .with_location(Location::from_span(source, offset..offset + 1))
+        .into()
+}
+
+/// Encode an object as an INI file
+///
+/// Scalars and arrays are keys, objects are sections and objects in a
+/// section are subsections. Arrays are written as repeated keys, and values
+/// nested deeper as JSON. A key or section name that would not read back
+/// the same is an error.
+pub(crate) fn encode(value: &Val) -> Result<String, OqError> {
+    let Val::Obj(obj) = value else {
+        return Err(OqError::Encode(Format::Ini, "needs an object".to_string()));
+    };
+    let mut out = String::new();
+    let mut sections = Vec::new();
+    for (key, value) in obj.iter() {
+        match value {
+            Val::Obj(section) => sections.push((key_string(key), section)),
+            value => write_key(&mut out, &key_string(key), value)?,
+        }
+    }
+    for (name, section) in sections {
+        // Comments and quoted subsections would change the name read back
+        if name.is_empty()
+            || name != name.trim()
+            || name.contains([']', '\n', '\r'])
+            || strip_comment(&name) != name
+            || section_name(&name).1.is_some()
+        {
+            let message = format!("cannot encode `{}` as a section name", name);
+            return Err(OqError::Encode(Format::Ini, message));
+        }
+        let mut subsections = Vec::new();
+        let mut body = String::new();
+        for (key, value) in section.iter() {
+            match value {
+                Val::Obj(sub) => subsections.push((key_string(key), sub)),
+                value => write_key(&mut body, &key_string(key), value)?,
+            }
+        }
+        if !body.is_empty() || subsections.is_empty() {
+            write_header(&mut out, &format!("[{}]", name));
+            out.push_str(&body);
+        }
+        for (sub, section) in subsections {
+            write_header(&mut out, &format!("[{} {}]", name, quote(&sub)));
+            for (key, value) in section.iter() {
+                write_key(&mut out, &key_string(key), value)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn write_header(out: &mut String, header: &str) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(header);
+    out.push('\n');
+}
+
+fn write_key(out: &mut String, key: &str, value: &Val) -> Result<(), OqError> {
+    if !is_key(key) {
+        let message = format!("cannot encode `{}` as a key", key);
+        return Err(OqError::Encode(Format::Ini, message));
+    }
+    match value {
+        Val::Arr(values) => {
+            for value in values.iter() {
+                write_key(out, key, value)?;
+            }
+        }
+        value => {
+            let text = match value {
+                Val::Null => String::new(),
+                Val::Str(..) => key_string(value),
+                value => value.to_string(),
+            };
+            out.push_str(key);
+            out.push_str(" = ");
+            out.push_str(&value_text(&text));
+            out.push('\n');
+        }
+    }
+    Ok(())
+}
+
+/// Whether a key reads back as itself: keys cannot be quoted, so one that
+/// holds `=` or `:`, starts like a comment or a header, or ends in `[]`
+/// would be read as something else
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key == key.trim()
+        && !key.contains(['=', ':', '\n', '\r'])
+        && !key.starts_with([';', '#', '['])
+        && !key.ends_with("[]")
+}
+
+/// A value as written: quoted if it would not read back the same
+fn value_text(text: &str) -> String {
+    let needs_quotes = text != text.trim()
+        || text.starts_with(['"', '\''])
+        || text.ends_with('\\')
+        || text.contains(['\n', '\r', '\t'])
+        || strip_comment(text).len() != text.len();
+    if needs_quotes {
+        quote(text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::val::{json_to_val, val_to_json};
+    use serde_json::json;
+
+    fn parse_json(input: &str, options: &InputOptions) -> serde_json::Value {
+        val_to_json(&parse(input, options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let input = r#"; global settings
+root = true
+
+[core]
+	bare = false
+	editor = "vim -f"  # inline comment
+	shared
+
+[remote "origin"]
+	url = https://example.com/repo.git#main
+	fetch = +refs/heads/*:refs/remotes/origin/*
+	fetch = +refs/tags/*:refs/tags/*
+
+[Service]
+ExecStart=/usr/bin/app \
+  --port 8080
+extension[] = curl
+"#;
+        assert_eq!(
+            parse_json(input, &InputOptions::new()),
+            json!({
+                "root": "true",
+                "core": {"bare": "false", "editor": "vim -f", "shared": true},
+                "remote": {"origin": {
+                    "url": "https://example.com/repo.git#main",
+                    "fetch": ["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"],
+                }},
+                "Service": {"ExecStart": "/usr/bin/app --port 8080", "extension": ["curl"]},
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_infer_types() {
+        let options = InputOptions::new().with_infer_types(true);
+        assert_eq!(
+            parse_json("[a]\nn = 3\nok = true\nid = 007\nempty =\n", &options),
+            json!({"a": {"n": 3, "ok": true, "id": "007", "empty": null}})
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for input in ["[a\nb = 1", "= 1"] {
+            assert!(matches!(
+                parse(input, &InputOptions::new()),
+                Err(OqError::Parse(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let value = json_to_val(json!({
+            "root": true,
+            "core": {"editor": "vim -f ; x", "n": 1, "tags": ["a", "b"]},
+            "remote": {"origin": {"url": "https://x"}},
+        }));
+        let text = encode(&value).unwrap();
+        assert_eq!(
+            text,
+            "root = true\n\n[core]\neditor = \"vim -f ; x\"\nn = 1\ntags = a\ntags = b\n\n[remote \"origin\"]\nurl = https://x\n"
+        );
+        let options = InputOptions::new().with_infer_types(true);
+        assert_eq!(parse(&text, &options).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encode_rejects_keys_that_do_not_read_back() {
+        for value in [
+            json!({"a=b": 1}),
+            json!({"s": {"a:b": 1}}),
+            json!({"; x": 1}),
+            json!({"[x]": 1}),
+            json!({"ext[]": 1}),
+            json!({"a]b": {"k": 1}}),
+            json!({"a #b": {"k": 1}}),
+            json!({"x \"y\"": {"k": 1}}),
+        ] {
+            assert!(
+                matches!(
+                    encode(&json_to_val(value.clone())),
+                    Err(OqError::Encode(Format::Ini, _))
+                ),
+                "{}",
+                value
+            );
+        }
+    }
+}
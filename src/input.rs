@@ -0,0 +1,130 @@
+//! Input stream shared by the main loop and the `input`/`inputs` builtins
+
+use crate::convert::{stop_after_error, Format};
+use crate::OqError;
+use jaq_json::Val;
+use std::cell::RefCell;
+
+/// A document read from an input
+#[derive(Debug, Clone)]
+pub struct InputDoc {
+    pub value: Val,
+    /// Path of the file the document came from (`None` for stdin)
+    pub filename: Option<String>,
+    /// Format the document was parsed as
+    pub format: Format,
+}
+
+/// Documents shared between the caller and a running filter
+///
+/// The caller takes documents with [`InputStream::next_doc`] to run the
+/// filter on, and the filter takes the following ones with `input` and
+/// `inputs`, like jq. `input_filename` reports the file of the document
+/// taken last. The stream ends after the first error, unless it was
+/// created with [`InputStream::keep_going`].
+pub struct InputStream<'a> {
+    docs: RefCell<Box<dyn Iterator<Item = Result<InputDoc, OqError>> + 'a>>,
+    filename: RefCell<Option<String>>,
+    /// Error hit by `input`/`inputs`, kept so that it is reported as-is
+    /// rather than as a filter error
+    error: RefCell<Option<OqError>>,
+}
+
+impl<'a> InputStream<'a> {
+    pub fn new(docs: impl Iterator<Item = Result<InputDoc, OqError>> + 'a) -> Self {
+        Self {
+            docs: RefCell::new(stop_after_error(docs)),
+            filename: RefCell::new(None),
+            error: RefCell::new(None),
+        }
+    }
+
+    /// A stream that goes on past errors, for callers that report them
+    /// and carry on with the next document
+    pub fn keep_going(docs: impl Iterator<Item = Result<InputDoc, OqError>> + 'a) -> Self {
+        Self {
+            docs: RefCell::new(Box::new(docs)),
+            filename: RefCell::new(None),
+            error: RefCell::new(None),
+        }
+    }
+
+    /// Take the next document
+    pub fn next_doc(&self) -> Option<Result<InputDoc, OqError>> {
+        let doc = self.docs.borrow_mut().next()?;
+        if let Ok(doc) = &doc {
+            self.filename.replace(doc.filename.clone());
+        }
+        Some(doc)
+    }
+
+    /// Path of the file of the document taken last
+    pub fn filename(&self) -> Option<String> {
+        self.filename.borrow().clone()
+    }
+
+    /// Take the next value for `input`/`inputs`
+    pub(crate) fn next_value(&self) -> Option<Result<Val, String>> {
+        match self.next_doc()? {
+            Ok(doc) => Some(Ok(doc.value)),
+            Err(e) => {
+                let message = e.to_string();
+                self.error.replace(Some(e));
+                Some(Err(message))
+            }
+        }
+    }
+
+    /// The error hit by `input`/`inputs`, if any
+    pub(crate) fn take_error(&self) -> Option<OqError> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(n: isize, filename: &str) -> Result<InputDoc, OqError> {
+        Ok(InputDoc {
+            value: Val::from(n),
+            filename: Some(filename.to_string()),
+            format: Format::Json,
+        })
+    }
+
+    #[test]
+    fn test_stream_tracks_filename() {
+        let stream = InputStream::new(vec![doc(1, "a.json"), doc(2, "b.json")].into_iter());
+        assert_eq!(stream.filename(), None);
+        assert_eq!(stream.next_doc().unwrap().unwrap().value, Val::from(1isize));
+        assert_eq!(stream.filename().as_deref(), Some("a.json"));
+        assert_eq!(stream.next_value(), Some(Ok(Val::from(2isize))));
+        assert_eq!(stream.filename().as_deref(), Some("b.json"));
+        assert!(stream.next_doc().is_none());
+    }
+
+    #[test]
+    fn test_stream_keeps_input_errors() {
+        let docs = vec![
+            doc(1, "a.json"),
+            Err(OqError::TomlParse("bad".to_string())),
+            doc(2, "b.json"),
+        ];
+        let stream = InputStream::new(docs.into_iter());
+        assert!(stream.next_value().unwrap().is_ok());
+        assert!(stream.next_value().unwrap().is_err());
+        assert!(matches!(stream.take_error(), Some(OqError::TomlParse(_))));
+        // The stream ends after the first error
+        assert!(stream.next_value().is_none());
+    }
+
+    #[test]
+    fn test_keep_going_stream_continues_after_errors() {
+        let docs = vec![Err(OqError::TomlParse("bad".to_string())), doc(2, "b.json")];
+        let stream = InputStream::keep_going(docs.into_iter());
+        assert!(stream.next_doc().unwrap().is_err());
+        assert_eq!(stream.next_doc().unwrap().unwrap().value, Val::from(2isize));
+        assert!(stream.next_doc().is_none());
+    }
+}
@@ -0,0 +1,293 @@
+//! JSON5 and JSONC: JSON with comments, trailing commas and unquoted keys
+//!
+//! Input is read with the `json5` crate, which also accepts JSONC (JSON with
+//! `//` and `/* */` comments and trailing commas, as used by `tsconfig.json`
+//! and editor settings). Output is JSON with the keys that are valid
+//! identifiers left unquoted.
+
+use crate::convert::Format;
+use crate::diagnostic::Diagnostic;
+use crate::OqError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::ser::{CharEscape, CompactFormatter, Formatter, PrettyFormatter};
+use std::io::{self, Write};
+
+/// Deserialize a JSON5 document
+pub(crate) fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, OqError> {
+    ::json5::from_str(input).map_err(|e| Diagnostic::from_json5(input, &e).into())
+}
+
+/// Whether a JSON5 parse error of `input` is about a number out of range
+///
+/// The error then points at a well-formed number literal that does not fit
+/// in an `i64`, or in an `f64` without becoming infinite. The whole text has
+/// been parsed by then, so it is JSON5 apart from the number (unlike strict
+/// JSON, the parser has no arbitrary precision).
+pub(crate) fn is_range_error(input: &str, error: &OqError) -> bool {
+    let OqError::Parse(diagnostic) = error else {
+        return false;
+    };
+    let Some(start) = diagnostic
+        .location
+        .as_ref()
+        .map(|location| location.span.start)
+    else {
+        return false;
+    };
+    let rest = input.get(start..).unwrap_or_default();
+    let len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')))
+        .unwrap_or(rest.len());
+    is_out_of_range(&rest[..len])
+}
+
+/// Whether a number literal is well-formed but out of range
+fn is_out_of_range(literal: &str) -> bool {
+    let (sign, digits) = match literal.strip_prefix(['+', '-']) {
+        Some(digits) => (&literal[..1], digits),
+        None => ("", literal),
+    };
+    if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        return !hex.is_empty()
+            && hex.chars().all(|c| c.is_ascii_hexdigit())
+            && i64::from_str_radix(&format!("{}{}", sign, hex), 16).is_err();
+    }
+    // `Infinity` and `NaN` are literals of their own
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return false;
+    }
+    if digits.chars().all(|c| c.is_ascii_digit()) {
+        return literal.parse::<i64>().is_err();
+    }
+    matches!(literal.parse::<f64>(), Ok(f) if f.is_infinite())
+}
+
+/// Serialize a value as JSON5, indented with `indent` or on a single line
+pub(crate) fn to_string<T: Serialize + ?Sized>(
+    value: &T,
+    indent: Option<&str>,
+) -> Result<String, OqError> {
+    let mut buf = Vec::new();
+    match indent {
+        Some(indent) => {
+            let formatter = Json5Formatter::new(PrettyFormatter::with_indent(indent.as_bytes()));
+            value
+                .serialize(&mut serde_json::Serializer::with_formatter(
+                    &mut buf, formatter,
+                ))
+                .map_err(|e| OqError::Encode(Format::Json5, e.to_string()))?;
+        }
+        None => {
+            let formatter = Json5Formatter::new(CompactFormatter);
+            value
+                .serialize(&mut serde_json::Serializer::with_formatter(
+                    &mut buf, formatter,
+                ))
+                .map_err(|e| OqError::Encode(Format::Json5, e.to_string()))?;
+        }
+    }
+    Ok(String::from_utf8(buf).expect("serde_json writes UTF-8"))
+}
+
+/// Wraps a JSON formatter to write object keys without quotes where they
+/// are identifiers
+///
+/// A key is held back until it ends, or until it needs an escape, in which
+/// case it is written quoted.
+struct Json5Formatter<F> {
+    inner: F,
+    /// The key being written, while it may still go without quotes
+    key: Option<String>,
+    in_key: bool,
+}
+
+impl<F: Formatter> Json5Formatter<F> {
+    fn new(inner: F) -> Self {
+        Self {
+            inner,
+            key: None,
+            in_key: false,
+        }
+    }
+}
+
+/// Whether a key can be written without quotes (an ECMAScript 5
+/// IdentifierName, limited to ASCII)
+fn is_identifier(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+impl<F: Formatter> Formatter for Json5Formatter<F> {
+    fn begin_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.in_key {
+            self.key = Some(String::new());
+            return Ok(());
+        }
+        self.inner.begin_string(writer)
+    }
+
+    fn end_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        match self.key.take() {
+            Some(key) if is_identifier(&key) => writer.write_all(key.as_bytes()),
+            Some(key) => {
+                self.inner.begin_string(writer)?;
+                self.inner.write_string_fragment(writer, &key)?;
+                self.inner.end_string(writer)
+            }
+            None => self.inner.end_string(writer),
+        }
+    }
+
+    fn write_string_fragment<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        match &mut self.key {
+            Some(key) => {
+                key.push_str(fragment);
+                Ok(())
+            }
+            None => self.inner.write_string_fragment(writer, fragment),
+        }
+    }
+
+    fn write_char_escape<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        char_escape: CharEscape,
+    ) -> io::Result<()> {
+        // A key with escapes is no identifier: write what is held back
+        if let Some(key) = self.key.take() {
+            self.inner.begin_string(writer)?;
+            self.inner.write_string_fragment(writer, &key)?;
+        }
+        self.inner.write_char_escape(writer, char_escape)
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.inner.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.in_key = true;
+        self.inner.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.in_key = false;
+        self.inner.end_object_key(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object_value(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::val::{DeVal, SerVal};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_comments_and_trailing_commas() {
+        let input = r#"{
+  // TypeScript settings
+  "compilerOptions": {
+    "strict": true, /* block */
+    "paths": ["src/*",],
+  },
+  unquoted: 'single',
+  hex: 0x10,
+}"#;
+        let value: serde_json::Value = from_str(input).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "compilerOptions": {"strict": true, "paths": ["src/*"]},
+                "unquoted": "single",
+                "hex": 16,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let Err(OqError::Parse(diagnostic)) = from_str::<DeVal>("{\n  a: 1\n  b: 2\n}") else {
+            panic!("expected a parse error");
+        };
+        // The parser stops where a comma or `}` was due
+        assert_eq!(diagnostic.message, "expected boolean, null, or string");
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (2, 6));
+    }
+
+    #[test]
+    fn test_integer_out_of_range() {
+        let range_error =
+            |input| is_range_error(input, &from_str::<serde_json::Value>(input).unwrap_err());
+        assert!(range_error("{a: 12345678901234567890}"));
+        assert!(range_error("[1,\n  -0x8000000000000001]"));
+        assert!(range_error("{a: 1e999}"));
+        assert!(!range_error("{a: }"));
+        assert!(!range_error("{a: 12x}"));
+    }
+
+    #[test]
+    fn test_unquoted_keys() {
+        let value = crate::val::json_to_val(json!({
+            "name": "oq",
+            "$ref": 1,
+            "two words": [1, {"a-b": null, "_c": "\"x\""}],
+            "tab\tkey": true,
+        }));
+        assert_eq!(
+            to_string(&SerVal(&value), None).unwrap(),
+            r#"{name:"oq",$ref:1,"two words":[1,{"a-b":null,_c:"\"x\""}],"tab\tkey":true}"#
+        );
+        assert_eq!(
+            to_string(&SerVal(&value), Some("  ")).unwrap(),
+            "{\n  name: \"oq\",\n  $ref: 1,\n  \"two words\": [\n    1,\n    {\n      \"a-b\": null,\n      _c: \"\\\"x\\\"\"\n    }\n  ],\n  \"tab\\tkey\": true\n}"
+        );
+    }
+}
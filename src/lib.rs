@@ -1,6 +1,7 @@
 //! oq - Object Query
 //!
-//! A jq-like tool for querying JSON, YAML, TOML, and TOON data.
+//! A jq-like tool for querying JSON, JSON5, YAML, TOML, TOON, CSV, TSV, XML, INI
+//! and dotenv data.
 //!
 //! # Example
 //!
@@ -23,24 +24,55 @@
 //! ```
 
 mod convert;
+mod delimited;
+mod detect;
+mod diagnostic;
+mod dotenv;
 mod formatter;
+mod highlight;
+mod ini;
+mod input;
+mod json5;
+mod locate;
 mod mapper;
 mod query;
+mod shard;
 mod tabular;
+mod val;
+mod walk;
+mod xml;
 
 pub use convert::{
-    detect_format, encode_to_format, parse_auto, parse_input, to_json, to_toml, to_toon, to_yaml,
-    Format, InputFormat, OutputFormat,
+    detect_format, encode_to_format, encode_to_format_with, encode_val_to_format,
+    encode_val_to_format_with, parse_auto, parse_input, parse_input_val, parse_raw_lines,
+    parse_stream, parse_stream_val, parse_stream_val_with, to_json, to_toml, to_toon, to_yaml,
+    Documents, Format, Indent, InputFormat, InputOptions, OutputFormat, OutputOptions,
+    ValDocuments,
 };
+pub use detect::{explain_format, explain_format_start, Detection, FormatScore};
+pub use diagnostic::{CompileError, Diagnostic, FilterError, Location};
 pub use formatter::{
     command_formatter, read_note_formatter, search_formatter, ContentFormatter, FieldFormat,
 };
+pub use highlight::{highlight, ColorScheme, Token};
+pub use input::{InputDoc, InputStream};
 pub use mapper::{
     default_registry, ChainMapper, FieldSelectMapper, IdentityMapper, JqMapper, LimitMapper,
     Mapper, MapperRegistry, TruncateMapper,
 };
-pub use query::{compile_filter, run_filter, CompiledFilter};
+pub use query::{
+    compile_filter, compile_filter_from_path, compile_filter_with_paths, compile_filter_with_vars,
+    default_search_paths, run_filter, run_filter_iter, run_filter_val_iter, run_filter_with_inputs,
+    run_filter_with_vars, CompiledFilter, FilterVars, Results, ValResults,
+};
+pub use shard::{Shard, Shards};
 pub use tabular::encode_table;
+pub use val::{json_to_val, key_string, val_to_json};
+pub use walk::FileFinder;
+
+/// jaq's value type, used for parsing, filtering and encoding without
+/// a `serde_json::Value` round-trip
+pub use jaq_json::Val;
 
 use thiserror::Error;
 
@@ -61,16 +93,120 @@ pub enum OqError {
     #[error("TOON parse error: {0}")]
     ToonParse(String),
 
-    #[error("Filter error: {0}")]
-    Filter(String),
+    /// A value that cannot be written in the output format
+    #[error("{} output error: {}", .0.name().to_uppercase(), .1)]
+    Encode(Format, String),
+
+    /// An input that failed to parse, with its location
+    #[error("{0}")]
+    Parse(Box<Diagnostic>),
+
+    /// A filter that failed while running, with where it failed
+    #[error("{0}")]
+    Filter(Box<FilterError>),
+
+    /// A filter that failed to load or compile, with every error found
+    #[error("{}", display_lines(.0))]
+    Compile(Vec<CompileError>),
+
+    #[error("Usage error: {0}")]
+    Usage(String),
+}
+
+impl OqError {
+    /// Process exit code for this error
+    ///
+    /// | Code | Meaning                                        |
+    /// |------|------------------------------------------------|
+    /// | 1    | `--exit-status`: last output was false or null |
+    /// | 2    | Usage error (bad arguments)                    |
+    /// | 3    | Filter failed to compile                       |
+    /// | 4    | `--exit-status`: no output was produced        |
+    /// | 5    | Runtime filter error                           |
+    /// | 6    | Input could not be parsed or output encoded    |
+    /// | 7    | IO error                                       |
+    ///
+    /// Codes 1-5 match jq.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OqError::Usage(_) => 2,
+            OqError::Compile(_) => 3,
+            OqError::Filter(_) => 5,
+            OqError::JsonParse(_)
+            | OqError::YamlParse(_)
+            | OqError::TomlParse(_)
+            | OqError::ToonParse(_)
+            | OqError::Encode(..)
+            | OqError::Parse(_) => 6,
+            OqError::Io(_) => 7,
+        }
+    }
+
+    /// Name of the variant, e.g. `Parse` or `Compile`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OqError::Io(_) => "Io",
+            OqError::JsonParse(_) => "JsonParse",
+            OqError::YamlParse(_) => "YamlParse",
+            OqError::TomlParse(_) => "TomlParse",
+            OqError::ToonParse(_) => "ToonParse",
+            OqError::Encode(..) => "Encode",
+            OqError::Parse(_) => "Parse",
+            OqError::Filter(_) => "Filter",
+            OqError::Compile(_) => "Compile",
+            OqError::Usage(_) => "Usage",
+        }
+    }
+
+    /// The error as JSON objects, one per error (a filter can fail to
+    /// compile for several reasons)
+    ///
+    /// Every object has `kind` and `message`. Parse and compile errors add
+    /// `file`, `line`, `column` and the byte `span` of the error; filter
+    /// errors add `file`, `path`, `value`, `expression` and the
+    /// `expression_location` of the failing expression in the filter.
+    pub fn to_json(&self) -> Vec<serde_json::Value> {
+        match self {
+            OqError::Parse(diagnostic) => vec![diagnostic.to_json()],
+            OqError::Compile(errors) => errors.iter().map(CompileError::to_json).collect(),
+            OqError::Filter(error) => vec![error.to_json()],
+            e => vec![serde_json::json!({"kind": e.kind(), "message": e.to_string()})],
+        }
+    }
+
+    /// Attach the path of the input to a parse or filter error that has none
+    pub fn with_path(self, path: impl Into<String>) -> Self {
+        match self {
+            OqError::Parse(diagnostic) if diagnostic.path.is_none() => {
+                diagnostic.with_path(path).into()
+            }
+            OqError::Filter(error) if error.file.is_none() => error.with_file(path).into(),
+            e => e,
+        }
+    }
+}
 
-    #[error("Query compile error: {0}")]
-    Compile(String),
+/// Display each item on its own line
+fn display_lines<T: std::fmt::Display>(items: &[T]) -> String {
+    let lines: Vec<String> = items.iter().map(T::to_string).collect();
+    lines.join("\n")
+}
+
+impl From<FilterError> for OqError {
+    fn from(error: FilterError) -> Self {
+        OqError::Filter(Box::new(error))
+    }
+}
+
+impl From<Diagnostic> for OqError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        OqError::Parse(Box::new(diagnostic))
+    }
 }
 
 /// Convert a JSON value to TOON string
 pub fn json_to_toon(value: serde_json::Value) -> Result<String, OqError> {
-    toon_format::encode_default(&value).map_err(|e| OqError::ToonParse(e.to_string()))
+    toon_format::encode_default(&value).map_err(|e| OqError::Encode(Format::Toon, e.to_string()))
 }
 
 /// Convert a JSON value to TOON string, applying a mapper first
@@ -0,0 +1,299 @@
+//! Locating runtime errors in the input and in the filter
+//!
+//! jaq only reports the message of a runtime error. To find where it
+//! happened, the filter is split into the stages of its top-level pipeline
+//! (`.items[] | .spec | .replicas + 1`), and the stages are replayed on the
+//! input one at a time, tracking the path of each value with `path(...)`.
+//! The first stage that fails, in jq's evaluation order, is the failing
+//! expression, and the value it was given is the offending value.
+//!
+//! A replay only happens after an error, but it starts over from the
+//! document: each stage runs once for its values and once more for their
+//! paths, so locating an error can cost about twice the run that raised it.
+//! Replays cannot take documents from the input stream again, so filters
+//! that read it are not replayed; `input_filename` still reports the file of
+//! the document that failed.
+
+use crate::diagnostic::{FilterError, Location};
+use crate::query::{compile_source, run_raw, CompiledFilter, FilterSource, InputSource};
+use crate::val::val_to_json;
+use jaq_json::Val;
+use serde_json::Value;
+use std::ops::Range;
+
+/// Longest rendering of the offending value, in characters
+const VALUE_WIDTH: usize = 80;
+
+/// A stage of the pipeline, compiled to produce its outputs and their paths
+pub(crate) struct Stage {
+    code: String,
+    /// Byte range of the stage in the filter
+    span: Range<usize>,
+    values: CompiledFilter,
+    /// `None` if the stage cannot be compiled as a path expression
+    paths: Option<CompiledFilter>,
+}
+
+/// Where an error happened: the stage, and the path and value it ran on
+struct Found {
+    stage: usize,
+    path: Option<Vec<Value>>,
+    value: Val,
+}
+
+/// The input stream as a replay sees it: no documents, only the file name
+struct Replayed(Option<String>);
+
+impl InputSource for Replayed {
+    fn next_value(&self) -> Option<Result<Val, String>> {
+        None
+    }
+
+    fn filename(&self) -> Option<String> {
+        self.0.clone()
+    }
+}
+
+/// Build the error for `message`, raised while running `filter` on `input`
+pub(crate) fn locate_error(
+    filter: &CompiledFilter,
+    input: &Val,
+    values: &[Val],
+    inputs: Option<&dyn InputSource>,
+    message: String,
+) -> FilterError {
+    let error = FilterError::new(message);
+    let code = filter.source().code.as_str();
+    // Replaying would take documents from the input stream again
+    if filter.reads_inputs() {
+        return error;
+    }
+    let Some(stages) = filter.stages.get_or_init(|| compile_stages(filter)) else {
+        return error;
+    };
+    let inputs = Replayed(inputs.and_then(InputSource::filename));
+    let Some(found) = replay(stages, values, &inputs, input.clone(), Some(Vec::new())) else {
+        return error;
+    };
+
+    let mut error = error.with_value(render(&found.value));
+    if let Some(path) = found.path {
+        error = error.with_path(path);
+    }
+    let stage = &stages[found.stage];
+    if stages.len() > 1 {
+        error = error.with_expression(stage.code.as_str());
+    }
+    error = error.with_location(Location::from_span(code, stage.span.clone()));
+    let filter_path = &filter.source().path;
+    if !filter_path.as_os_str().is_empty() {
+        error = error.with_filter_path(filter_path.display().to_string());
+    }
+    error
+}
+
+/// Compile the stages of the filter's pipeline, or `None` if one of them
+/// does not compile on its own
+fn compile_stages(filter: &CompiledFilter) -> Option<Vec<Stage>> {
+    let compile = |code: String| {
+        let source = FilterSource {
+            code,
+            ..filter.source().clone()
+        };
+        compile_source(source, filter.var_names().to_vec()).ok()
+    };
+    let code = filter.source().code.as_str();
+    split_pipeline(code)
+        .into_iter()
+        .map(|stage| {
+            // Stages are slices of the code
+            let start = stage.as_ptr() as usize - code.as_ptr() as usize;
+            Some(Stage {
+                code: stage.to_string(),
+                span: start..start + stage.len(),
+                values: compile(stage.to_string())?,
+                paths: compile(format!("path({})", stage)),
+            })
+        })
+        .collect()
+}
+
+/// Run the stages depth-first, like jq, until one of them fails
+fn replay(
+    stages: &[Stage],
+    values: &[Val],
+    inputs: &Replayed,
+    input: Val,
+    path: Option<Vec<Value>>,
+) -> Option<Found> {
+    let (stage, rest) = stages.split_first()?;
+    let mut paths = match (&path, &stage.paths) {
+        (Some(_), Some(filter)) => Some(run_raw(
+            filter,
+            input.clone(),
+            values.to_vec(),
+            Some(inputs),
+        )),
+        _ => None,
+    };
+    for output in run_raw(&stage.values, input.clone(), values.to_vec(), Some(inputs)) {
+        let output = match output {
+            Ok(output) => output,
+            Err(_) => {
+                return Some(Found {
+                    stage: 0,
+                    path,
+                    value: input,
+                })
+            }
+        };
+        // A stage that is not a path expression loses track of the path
+        let sub_path = match (paths.as_mut().and_then(Iterator::next), &path) {
+            (Some(Ok(sub)), Some(path)) => match val_to_json(&sub) {
+                Ok(Value::Array(sub)) => Some(path.iter().cloned().chain(sub).collect()),
+                _ => None,
+            },
+            _ => {
+                paths = None;
+                None
+            }
+        };
+        if let Some(found) = replay(rest, values, inputs, output, sub_path) {
+            return Some(Found {
+                stage: found.stage + 1,
+                ..found
+            });
+        }
+    }
+    None
+}
+
+/// Compact JSON for a value, cut to [`VALUE_WIDTH`] characters
+fn render(value: &Val) -> String {
+    let text = val_to_json(value)
+        .ok()
+        .and_then(|json| serde_json::to_string(&json).ok())
+        .unwrap_or_else(|| value.to_string());
+    if text.chars().count() <= VALUE_WIDTH {
+        return text;
+    }
+    let cut: String = text.chars().take(VALUE_WIDTH - 3).collect();
+    format!("{}...", cut)
+}
+
+/// Split a filter into the stages of its top-level pipeline
+///
+/// Pipes inside brackets, strings, `if ... end` and comments do not split.
+/// A binding (`as $x`, `label $x`) scopes over the rest of the filter, so the
+/// rest stays one stage. Filters with definitions or imports are not split.
+fn split_pipeline(code: &str) -> Vec<&str> {
+    #[derive(PartialEq)]
+    enum Open {
+        Bracket,
+        Interpolation,
+    }
+
+    let mut stages = Vec::new();
+    let mut stack = Vec::new();
+    let mut in_string = false;
+    let mut keywords = 0usize;
+    let mut start = 0;
+    let mut chars = code.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if in_string {
+            match c {
+                '\\' => {
+                    if let Some((_, '(')) = chars.next() {
+                        stack.push(Open::Interpolation);
+                        in_string = false;
+                    }
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '#' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+            '(' | '[' | '{' => stack.push(Open::Bracket),
+            ')' | ']' | '}' => in_string = stack.pop() == Some(Open::Interpolation),
+            // `|=` is an update, not a pipe
+            '|' if stack.is_empty()
+                && keywords == 0
+                && chars.next_if(|&(_, c)| c == '=').is_none() =>
+            {
+                stages.push(&code[start..i]);
+                start = i + 1;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    end = j + c.len_utf8();
+                }
+                // Keywords only count outside of field names like `.if`
+                if code[..i].ends_with('.') || !stack.is_empty() {
+                    continue;
+                }
+                match &code[i..end] {
+                    "if" => keywords += 1,
+                    "end" => keywords = keywords.saturating_sub(1),
+                    "def" | "import" | "include" => return vec![code.trim()],
+                    "as" | "label" if keywords == 0 => {
+                        stages.push(&code[start..]);
+                        start = code.len();
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    if start < code.len() {
+        stages.push(&code[start..]);
+    }
+    let stages: Vec<&str> = stages.into_iter().map(str::trim).collect();
+    if stages.iter().any(|stage| stage.is_empty()) {
+        return vec![code.trim()];
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_pipeline() {
+        assert_eq!(
+            split_pipeline(".items[] | .spec | .replicas + 1"),
+            [".items[]", ".spec", ".replicas + 1"]
+        );
+        assert_eq!(
+            split_pipeline("map(.a | .b) | \"x|\\(.c | .d)|\" | .e |= 1"),
+            ["map(.a | .b)", "\"x|\\(.c | .d)|\"", ".e |= 1"]
+        );
+        assert_eq!(
+            split_pipeline(".[] | if . then .a | .b else . end | .c"),
+            [".[]", "if . then .a | .b else . end", ".c"]
+        );
+        assert_eq!(
+            split_pipeline(".a | .b as $x | .c | $x"),
+            [".a", ".b as $x | .c | $x"]
+        );
+        assert_eq!(
+            split_pipeline("def f: .a | .b; f | .c"),
+            ["def f: .a | .b; f | .c"]
+        );
+        assert_eq!(split_pipeline(".a # x | y\n| .b"), [".a # x | y", ".b"]);
+    }
+
+    #[test]
+    fn test_render_truncates() {
+        let value = Val::from("x".repeat(200));
+        let text = render(&value);
+        assert_eq!(text.chars().count(), VALUE_WIDTH);
+        assert!(text.ends_with("..."));
+    }
+}
@@ -23,32 +23,56 @@
 //! cat data.yaml | oq '.items[]' -o json
 //! ```
 
-use clap::Parser;
-use std::io::{self, Read, Write};
+use clap::{CommandFactory, FromArgMatches, Parser};
 use oq::{
-    compile_filter, encode_to_format, parse_input, run_filter, CompiledFilter, Format,
-    InputFormat, OqError, OutputFormat,
+    compile_filter_from_path, compile_filter_with_paths, default_search_paths,
+    encode_val_to_format_with, explain_format, explain_format_start, highlight, key_string,
+    parse_raw_lines, parse_stream_val_with, run_filter_with_inputs, val_to_json, ColorScheme,
+    CompiledFilter, FileFinder, FilterError, FilterVars, Format, Indent, InputDoc, InputFormat,
+    InputOptions, InputStream, OqError, OutputFormat, OutputOptions, Shard, Shards, Val,
+    ValDocuments, ValResults,
 };
+use self_cell::self_cell;
+use std::cell::{Cell, RefCell};
+use std::io::{self, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
 
 #[derive(Parser, Debug)]
 #[command(name = "oq")]
-#[command(about = "Object Query - jq for JSON, YAML, TOML, and TOON")]
+#[command(
+    about = "Object Query - jq for JSON, JSON5, YAML, TOML, TOON, CSV, TSV, XML, INI and dotenv"
+)]
 #[command(version)]
 #[command(after_help = "EXAMPLES:
     oq '.name' data.json          Query JSON file
     cat config.yaml | oq '.db'    Query YAML from stdin
     oq '.deps' Cargo.toml         Query TOML file
     oq -o yaml '.users' data.json Convert query result to YAML
-    oq '.' data.yaml -o json      Convert YAML to JSON")]
+    oq '.' data.yaml -o json      Convert YAML to JSON
+    oq -o csv '.users' data.json  Write an array of objects as CSV rows
+    oq -o env '.db' app.yaml      Write an object as KEY=value lines for eval
+    oq -o json5 . tsconfig.json   Write JSON5, with unquoted keys
+    oq -n --arg v 1.2 '{v: $v}'   Pass a string variable into the filter
+    oq -L lib -f query.jq a.yaml  Run a filter file that imports modules from lib/")]
 struct Cli {
     /// jq filter expression (default: identity ".")
-    #[arg(default_value = ".")]
-    filter: String,
+    filter: Option<String>,
 
-    /// Input files (reads from stdin if not specified)
+    /// Input files, optionally as FORMAT:FILE (reads from stdin if not specified)
     #[arg(value_name = "FILE")]
     files: Vec<String>,
 
+    /// Read the filter from FILE; the first argument is then an input file
+    #[arg(short = 'f', long = "from-file", value_name = "FILE")]
+    from_file: Option<PathBuf>,
+
+    /// Search DIR for modules (replaces the default ~/.jq, $ORIGIN/../lib/jq, $ORIGIN/../lib)
+    #[arg(short = 'L', long = "library-path", value_name = "DIR")]
+    library_paths: Vec<PathBuf>,
+
     /// Input format (default: auto-detect)
     #[arg(short = 'i', long = "input", value_name = "FORMAT")]
     input_format: Option<InputFormat>,
@@ -65,17 +89,174 @@ struct Cli {
     #[arg(short = 'c', long)]
     compact: bool,
 
-    /// Read entire input as single array (like jq -s)
+    /// Indent with tabs instead of spaces
+    #[arg(long, conflicts_with = "indent")]
+    tab: bool,
+
+    /// Indent with N spaces (0-7, default 2)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=7))]
+    indent: Option<u8>,
+
+    /// Sort the keys of objects, mappings and tables
+    #[arg(short = 'S', long)]
+    sort_keys: bool,
+
+    /// Like -r, but don't print a newline after each output
+    #[arg(short = 'j', long)]
+    join_output: bool,
+
+    /// Escape non-ASCII characters in JSON output
+    #[arg(short = 'a', long)]
+    ascii_output: bool,
+
+    /// Like -r, but print a NUL instead of a newline after each output
+    #[arg(long, conflicts_with = "join_output")]
+    raw_output0: bool,
+
+    /// CSV and TSV have no header row: input columns are keyed by index
+    /// and output has no header
+    #[arg(long)]
+    no_header: bool,
+
+    /// Read CSV, TSV and INI values that look like numbers, booleans or nothing
+    /// as numbers, booleans and null
+    #[arg(long)]
+    infer_types: bool,
+
+    /// Write these CSV and TSV columns of objects, in order (default: the
+    /// keys of the first object)
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    columns: Option<Vec<String>>,
+
+    /// Prefix env output lines with `export`
+    #[arg(long)]
+    export: bool,
+
+    /// Join the keys of nested objects with SEP in env output variable names
+    #[arg(long, value_name = "SEP", default_value = "__")]
+    env_separator: String,
+
+    /// Read all inputs into a single array (like jq -s)
     #[arg(short = 's', long)]
     slurp: bool,
 
+    /// Read each line of input as a string instead of parsing it (with -s, the whole input)
+    #[arg(short = 'R', long)]
+    raw_input: bool,
+
     /// Don't read any input, use null as input
     #[arg(short = 'n', long)]
     null_input: bool,
 
-    /// Colorize output (auto, always, never)
+    /// Set the exit status from the last output: 1 if false or null, 4 if none
+    #[arg(short = 'e', long)]
+    exit_status: bool,
+
+    /// Explain on stderr how the format of each input was detected
+    #[arg(long)]
+    explain_detect: bool,
+
+    /// Colorize output (auto, always, never); colors are set with OQ_COLORS
     #[arg(long, default_value = "auto")]
     color: ColorOption,
+
+    /// Report errors as text, JSON lines or a SARIF log
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    error_format: ErrorFormat,
+
+    /// Report inputs that fail to read, parse or filter, go on with the rest
+    /// and exit with the status of the first error
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Print the file of each result: as a FILE: prefix with -r, otherwise
+    /// as {"file": FILE, "value": RESULT}
+    #[arg(short = 'H', long)]
+    with_filename: bool,
+
+    /// Only read files matching GLOB from directories and globs (gitignore syntax)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files matching GLOB in directories and globs (gitignore syntax)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Also read hidden files and files ignored by .gitignore or .ignore
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Process files on N threads (default: one per core)
+    #[arg(long, value_name = "N")]
+    threads: Option<NonZeroUsize>,
+
+    /// Split each input into shards of documents and filter them in parallel
+    #[arg(long)]
+    parallel: bool,
+
+    /// Write results of parallel runs as soon as they are ready, not in input order
+    #[arg(long)]
+    unordered: bool,
+
+    /// Set variable $NAME to the string VALUE
+    #[arg(long = "arg", num_args = 2, value_names = ["NAME", "VALUE"])]
+    named_args: Vec<String>,
+
+    /// Set variable $NAME to the JSON value TEXT
+    #[arg(long = "argjson", num_args = 2, value_names = ["NAME", "TEXT"])]
+    named_json_args: Vec<String>,
+
+    /// Set variable $NAME to an array of the documents in FILE
+    #[arg(long = "slurpfile", num_args = 2, value_names = ["NAME", "FILE"])]
+    slurp_files: Vec<String>,
+
+    /// Set variable $NAME to the contents of FILE as a string
+    #[arg(long = "rawfile", num_args = 2, value_names = ["NAME", "FILE"])]
+    raw_files: Vec<String>,
+
+    /// Treat the arguments after this flag as positional strings ($ARGS.positional), not files
+    #[arg(long = "args", conflicts_with = "json_args")]
+    string_args: bool,
+
+    /// Treat the arguments after this flag as positional JSON values ($ARGS.positional), not files
+    #[arg(long = "jsonargs")]
+    json_args: bool,
+
+    /// How many of the filter and file arguments come before --args or
+    /// --jsonargs (all of them without either flag)
+    #[arg(skip)]
+    args_before_values: usize,
+}
+
+impl Cli {
+    /// Parse the command line, noting which arguments come before --args
+    /// and --jsonargs: as in jq, only the ones after the flag are values
+    fn try_parse_args() -> Result<Self, clap::Error> {
+        let matches = Cli::command().try_get_matches()?;
+        let mut cli = Cli::from_arg_matches(&matches)?;
+        let flag = if cli.string_args {
+            matches.index_of("string_args")
+        } else if cli.json_args {
+            matches.index_of("json_args")
+        } else {
+            None
+        };
+        cli.args_before_values = match flag {
+            Some(flag) => {
+                let filter = matches
+                    .index_of("filter")
+                    .filter(|_| cli.from_file.is_some());
+                let files = matches.indices_of("files").into_iter().flatten();
+                filter
+                    .into_iter()
+                    .chain(files)
+                    .filter(|&i| i < flag)
+                    .count()
+            }
+            None => usize::MAX,
+        };
+        Ok(cli)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
@@ -86,122 +267,1059 @@ enum ColorOption {
     Never,
 }
 
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+enum ErrorFormat {
+    /// `oq: message`, with a snippet where there is one
+    #[default]
+    Text,
+    /// One JSON object per error and line
+    Json,
+    /// A SARIF 2.1.0 log
+    Sarif,
+}
+
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("oq: {}", e);
-        std::process::exit(1);
+    let cli = match Cli::try_parse_args() {
+        Ok(cli) => cli,
+        Err(e) => usage_error(e),
+    };
+    let reporter = Reporter::new(cli.error_format, cli.keep_going);
+    let status = match run(cli, &reporter) {
+        Ok(status) => status,
+        // The reader went away (e.g. `oq ... | head`), which is not an error
+        Err(OqError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(e) => {
+            reporter.print(e);
+            0
+        }
+    };
+    // An error decides the exit status over --exit-status
+    match reporter.finish() {
+        Some(code) => std::process::exit(code),
+        None if status != 0 => std::process::exit(status),
+        None => {}
     }
 }
 
-fn run() -> Result<(), OqError> {
-    let cli = Cli::parse();
+/// Report a command line error in the format asked for with --error-format
+///
+/// Help, version and text errors are printed by clap as usual.
+fn usage_error(e: clap::Error) -> ! {
+    let format = error_format_arg().unwrap_or_default();
+    if matches!(format, ErrorFormat::Text) || !e.use_stderr() {
+        e.exit();
+    }
+    let text = e.render().to_string();
+    let message = text.lines().next().unwrap_or_default();
+    let reporter = Reporter::new(format, false);
+    reporter.print(OqError::Usage(
+        message
+            .strip_prefix("error: ")
+            .unwrap_or(message)
+            .to_string(),
+    ));
+    std::process::exit(reporter.finish().unwrap_or(2))
+}
+
+/// The value of --error-format on a command line that clap rejected
+fn error_format_arg() -> Option<ErrorFormat> {
+    use clap::ValueEnum;
+
+    let args: Vec<String> = std::env::args_os()
+        .skip(1)
+        .filter_map(|arg| arg.into_string().ok())
+        .collect();
+    let value = args
+        .iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.as_str() {
+            "--error-format" => args.get(i + 1).map(String::as_str),
+            arg => arg.strip_prefix("--error-format="),
+        })?;
+    ErrorFormat::from_str(value, false).ok()
+}
+
+/// Prints errors to stderr in the requested format
+///
+/// With --keep-going, errors about an input are reported as they happen and
+/// the run goes on with the next input.
+struct Reporter<'a> {
+    format: ErrorFormat,
+    keep_going: bool,
+    /// Exit code of the first error printed
+    status: Cell<Option<i32>>,
+    /// Errors kept for the SARIF log, which is printed once at the end
+    sarif: RefCell<Vec<OqError>>,
+    /// Warnings kept for the SARIF log
+    warnings: RefCell<Vec<String>>,
+    /// On a worker thread, where errors are kept until the file is written out
+    deferred: Option<&'a Deferred>,
+}
 
+impl<'a> Reporter<'a> {
+    fn new(format: ErrorFormat, keep_going: bool) -> Self {
+        Self {
+            format,
+            keep_going,
+            status: Cell::new(None),
+            sarif: RefCell::new(Vec::new()),
+            warnings: RefCell::new(Vec::new()),
+            deferred: None,
+        }
+    }
+
+    /// A reporter that keeps errors in `deferred`, in order with the output
+    fn deferred(keep_going: bool, deferred: &'a Deferred) -> Self {
+        Self {
+            deferred: Some(deferred),
+            ..Self::new(ErrorFormat::Text, keep_going)
+        }
+    }
+
+    /// Print the error and go on with --keep-going, otherwise fail with it
+    fn report(&self, e: OqError) -> Result<(), OqError> {
+        match e {
+            OqError::Io(e) if e.kind() == io::ErrorKind::BrokenPipe => Err(e.into()),
+            e if self.keep_going => {
+                match self.deferred {
+                    Some(deferred) => deferred.0.borrow_mut().push(Event::Error(e)),
+                    None => self.print(e),
+                }
+                Ok(())
+            }
+            e => Err(e),
+        }
+    }
+
+    /// Print an error, or keep it for the SARIF log
+    fn print(&self, e: OqError) {
+        if self.status.get().is_none() {
+            self.status.set(Some(e.exit_code()));
+        }
+        match self.format {
+            ErrorFormat::Text => eprintln!("oq: {}", e),
+            ErrorFormat::Json => {
+                for error in e.to_json() {
+                    eprintln!("{}", error);
+                }
+            }
+            ErrorFormat::Sarif => self.sarif.borrow_mut().push(e),
+        }
+    }
+
+    /// Print a warning, which does not change the exit code
+    fn warn(&self, message: &str) {
+        match self.format {
+            ErrorFormat::Text => eprintln!("oq: warning: {}", message),
+            ErrorFormat::Json => {
+                eprintln!(
+                    "{}",
+                    serde_json::json!({"kind": "Warning", "message": message})
+                );
+            }
+            ErrorFormat::Sarif => self.warnings.borrow_mut().push(message.to_string()),
+        }
+    }
+
+    /// Print the SARIF log, and return the exit code of the first error
+    fn finish(&self) -> Option<i32> {
+        let errors = self.sarif.borrow();
+        let warnings = self.warnings.borrow();
+        if !errors.is_empty() || !warnings.is_empty() {
+            eprintln!("{}", sarif_log(&warnings, &errors));
+        }
+        self.status.get()
+    }
+}
+
+/// A SARIF 2.1.0 log with one result per warning and per error
+fn sarif_log(warnings: &[String], errors: &[OqError]) -> serde_json::Value {
+    use serde_json::json;
+
+    let warnings = warnings.iter().map(|warning| {
+        json!({
+            "ruleId": "Warning",
+            "level": "warning",
+            "message": {"text": warning},
+        })
+    });
+    let results = errors.iter().flat_map(OqError::to_json).map(|error| {
+        let mut result = json!({
+            "ruleId": error["kind"],
+            "level": "error",
+            "message": {"text": error["message"]},
+        });
+        if let Some(file) = error["file"].as_str() {
+            let mut location = json!({"artifactLocation": {"uri": file}});
+            if let (Some(line), Some(column)) = (error["line"].as_u64(), error["column"].as_u64()) {
+                location["region"] = json!({"startLine": line, "startColumn": column});
+            }
+            result["locations"] = json!([{"physicalLocation": location}]);
+        }
+        // The failing part of the filter, for filter errors
+        if let Some(expression) = error["expression_location"].as_object() {
+            let artifact = match expression["file"].as_str() {
+                Some(file) => json!({"uri": file}),
+                None => json!({"description": {"text": "filter argument"}}),
+            };
+            result["relatedLocations"] = json!([{
+                "id": 0,
+                "message": {"text": "failing expression"},
+                "physicalLocation": {
+                    "artifactLocation": artifact,
+                    "region": {
+                        "startLine": expression["line"],
+                        "startColumn": expression["column"],
+                    },
+                },
+            }]);
+        }
+        result
+    });
+    let results: Vec<serde_json::Value> = warnings.chain(results).collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {"driver": {
+                "name": "oq",
+                "version": env!("CARGO_PKG_VERSION"),
+                "informationUri": env!("CARGO_PKG_REPOSITORY"),
+            }},
+            "results": results,
+        }],
+    })
+}
+
+/// Run oq and return the exit status for a successful run
+fn run(cli: Cli, reporter: &Reporter) -> Result<i32, OqError> {
     // Configure color output
     match cli.color {
         ColorOption::Always => yansi::enable(),
         ColorOption::Never => yansi::disable(),
         ColorOption::Auto => {
-            if !is_terminal() {
+            if !use_color() {
                 yansi::disable();
             }
         }
     }
+    let colors = if yansi::is_enabled() {
+        Some(ColorScheme::from_env().unwrap_or_else(|e| {
+            reporter.warn(&e);
+            ColorScheme::default()
+        }))
+    } else {
+        None
+    };
+    let printer = Printer::new(&cli, colors.as_ref());
+
+    // With -f the filter comes from a file, so every argument is an input
+    let args: Vec<String> = match cli.from_file {
+        Some(_) => cli.filter.iter().chain(&cli.files).cloned().collect(),
+        None => cli.files.clone(),
+    };
+
+    // Collect variables and compile the filter
+    let (inputs, values) = args.split_at(cli.args_before_values.min(args.len()));
+    let vars = filter_vars(&cli, values)?;
+    let search_paths = if cli.library_paths.is_empty() {
+        default_search_paths()
+    } else {
+        cli.library_paths.clone()
+    };
+    let filter = match (&cli.from_file, &cli.filter) {
+        // Like jq, a filter file that cannot be read is a usage error
+        (Some(path), _) => match compile_filter_from_path(path, &vars, &search_paths) {
+            Err(OqError::Io(e)) => {
+                return Err(OqError::Usage(format!("{}: {}", path.display(), e)));
+            }
+            result => result?,
+        },
+        (None, Some(code)) => compile_filter_with_paths(code, &vars, &search_paths)?,
+        (None, None) => compile_filter_with_paths(".", &vars, &search_paths)?,
+    };
 
-    // Compile the filter
-    let filter = compile_filter(&cli.filter)?;
+    // With --args/--jsonargs the arguments after the flag are values, not files
+    let files = find_files(&cli, inputs, reporter)?;
+
+    // Every input document goes through one stream, which the filter can also
+    // read from with `input`/`inputs`
+    let stream = if cli.raw_input && cli.slurp {
+        // The whole input, across all files, as a single string
+        InputStream::new(std::iter::once(read_all(&files).map(|text| InputDoc {
+            value: Val::from(text),
+            filename: None,
+            format: Format::Json,
+        })))
+    } else if cli.keep_going {
+        InputStream::keep_going(read_inputs(&cli, &files))
+    } else {
+        InputStream::new(read_inputs(&cli, &files))
+    };
 
     let stdout = io::stdout();
     let mut out = stdout.lock();
 
     if cli.null_input {
         // Process with null input
-        let results = run_filter(&filter, serde_json::Value::Null)?;
         let out_fmt = cli.output_format.map(Format::from).unwrap_or(Format::Json);
-        for value in results {
-            output_value(&mut out, &value, out_fmt, cli.raw, cli.compact)?;
+        let results = run_filter_with_inputs(&filter, Val::Null, &vars, &stream);
+        printer.print_results(&mut out, results, out_fmt, &stream, reporter)?;
+    } else if cli.slurp && !cli.raw_input {
+        // Collect every input into one array and run the filter once
+        let mut values = Vec::new();
+        let mut formats = Vec::new();
+        while let Some(doc) = stream.next_doc() {
+            match doc {
+                Ok(doc) => {
+                    values.push(doc.value);
+                    formats.push(doc.format);
+                }
+                Err(e) => reporter.report(e)?,
+            }
         }
-    } else if cli.files.is_empty() {
-        // Read from stdin
-        let input = read_stdin()?;
-        process_input(&mut out, &input, &cli, &filter)?;
+
+        // Determine output format: explicit > shared input format > json
+        let output_fmt = cli
+            .output_format
+            .map(Format::from)
+            .unwrap_or_else(|| common_format(&formats));
+
+        let input = Val::from_iter(values);
+        let results = run_filter_with_inputs(&filter, input, &vars, &stream);
+        printer.print_results(&mut out, results, output_fmt, &stream, reporter)?;
     } else {
-        // Read from files
-        for path in &cli.files {
-            let input = std::fs::read_to_string(path)?;
-            process_input(&mut out, &input, &cli, &filter)?;
-        }
+        let threads = cli
+            .threads
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get);
+        let jobs = Jobs {
+            cli: &cli,
+            filter: &filter,
+            vars: &vars,
+            colors: colors.as_ref(),
+            threads,
+            ordered: !cli.unordered,
+        };
+        // A filter that reads `inputs` has to see the documents in turn, and
+        // CSV and TSV rows share the header of the first object printed
+        let parallel = threads > 1 && !filter.reads_inputs() && !tabular_output(&cli, &files);
+        let last_truthy = if parallel && cli.parallel {
+            jobs.run(shards(&cli, &files), Jobs::run_shard, &mut out, reporter)?
+        } else if parallel && files.len() > 1 && !cli.explain_detect {
+            // Detection notes would interleave, so they keep files in turn
+            jobs.run(files.iter(), Jobs::run_file, &mut out, reporter)?
+        } else {
+            run_documents(&cli, &filter, &vars, &stream, &printer, &mut out, reporter)?;
+            printer.last_truthy.get()
+        };
+        printer.last_truthy.set(last_truthy);
     }
 
-    Ok(())
+    // With --exit-status the last output decides the status, like jq
+    Ok(match printer.last_truthy.get() {
+        _ if !cli.exit_status => 0,
+        Some(true) => 0,
+        Some(false) => 1,
+        None => 4,
+    })
 }
 
-fn process_input(
-    out: &mut impl Write,
-    input: &str,
+/// Whether the output may be CSV or TSV: explicitly, or from the format of
+/// an input
+fn tabular_output(cli: &Cli, files: &[FileArg]) -> bool {
+    let tabular = |format: Format| matches!(format, Format::Csv | Format::Tsv);
+    if let Some(format) = cli.output_format {
+        return tabular(format.into());
+    }
+    matches!(cli.input_format, Some(InputFormat::Csv | InputFormat::Tsv))
+        || files.iter().any(|file| {
+            file.format
+                .or_else(|| Format::from_path(&file.path))
+                .is_some_and(tabular)
+        })
+}
+
+/// Run the filter once per document of the stream, like jq
+fn run_documents(
     cli: &Cli,
     filter: &CompiledFilter,
+    vars: &FilterVars,
+    stream: &InputStream,
+    printer: &Printer,
+    out: &mut impl Write,
+    reporter: &Reporter,
 ) -> Result<(), OqError> {
-    // Detect input format (auto or explicit)
-    let input_fmt = cli
-        .input_format
-        .unwrap_or(InputFormat::Auto)
-        .detect(input);
+    while let Some(doc) = stream.next_doc() {
+        let doc = match doc {
+            Ok(doc) => doc,
+            Err(e) => {
+                reporter.report(e)?;
+                continue;
+            }
+        };
+
+        // Determine output format: explicit > input format > json
+        let output_fmt = cli
+            .output_format
+            .map(Format::from)
+            .unwrap_or(output_for(doc.format));
+
+        // Write each result as soon as the filter produces it
+        let results = run_filter_with_inputs(filter, doc.value, vars, stream);
+        printer.print_results(out, results, output_fmt, stream, reporter)?;
+    }
+    Ok(())
+}
 
-    // Parse input
-    let value = parse_input(input, input_fmt)?;
+/// What is needed to run the filter on worker threads
+#[derive(Clone, Copy)]
+struct Jobs<'a> {
+    cli: &'a Cli,
+    filter: &'a CompiledFilter,
+    vars: &'a FilterVars,
+    colors: Option<&'a ColorScheme>,
+    threads: usize,
+    /// Write outputs in the order of the work rather than as they are ready
+    ordered: bool,
+}
 
-    // Run the filter
-    let results = run_filter(filter, value)?;
+/// Output and errors of a worker thread's piece of work, in the order they
+/// happened
+#[derive(Default)]
+struct Deferred(RefCell<Vec<Event>>);
 
-    // Determine output format: explicit > input format > json
-    let output_fmt = cli
-        .output_format
-        .map(Format::from)
-        .unwrap_or(input_fmt);
+enum Event {
+    Output(Vec<u8>),
+    Error(OqError),
+}
 
-    // Output results
-    for value in results {
-        output_value(out, &value, output_fmt, cli.raw, cli.compact)?;
+impl Write for &Deferred {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut events = self.0.borrow_mut();
+        match events.last_mut() {
+            Some(Event::Output(output)) => output.extend_from_slice(buf),
+            _ => events.push(Event::Output(buf.to_vec())),
+        }
+        Ok(buf.len())
     }
 
-    Ok(())
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
-fn output_value(
-    out: &mut impl Write,
-    value: &serde_json::Value,
-    format: Format,
-    raw: bool,
-    compact: bool,
-) -> Result<(), OqError> {
-    // Raw string output (like jq -r)
-    if raw {
-        if let serde_json::Value::String(s) = value {
-            writeln!(out, "{}", s)?;
-            return Ok(());
+/// A piece of work done on a worker thread: its events and whether its
+/// last output was truthy
+type FileOutput = (Vec<Event>, Option<bool>);
+
+impl Jobs<'_> {
+    /// Run `process` over each piece of work on the worker threads
+    ///
+    /// Unless --unordered is given, outputs are written in the order of the
+    /// work as soon as everything before them is done, so the output is the
+    /// same as that of a run on one thread. Only a few outputs per thread
+    /// wait to be written at any time. Returns whether the last output
+    /// written was truthy.
+    fn run<W: Send>(
+        self,
+        work: impl Iterator<Item = W> + Send,
+        process: impl Fn(&Self, W) -> FileOutput + Sync,
+        out: &mut impl Write,
+        reporter: &Reporter,
+    ) -> Result<Option<bool>, OqError> {
+        let work = Mutex::new(work);
+        let window = self.threads * 2;
+        // In order, each piece of work queues the channel of its output
+        let (queue, queued) = mpsc::sync_channel::<mpsc::Receiver<FileOutput>>(window);
+        let (finished, results) = mpsc::sync_channel::<FileOutput>(window);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let (queue, finished) = (queue.clone(), finished.clone());
+                let (work, process) = (&work, &process);
+                scope.spawn(move || loop {
+                    let (item, done) = {
+                        let mut work = work.lock().unwrap_or_else(PoisonError::into_inner);
+                        let Some(item) = work.next() else {
+                            break;
+                        };
+                        if !self.ordered {
+                            (item, finished.clone())
+                        } else {
+                            let (done, output) = mpsc::sync_channel(1);
+                            // The receiver is gone once writing has failed
+                            if queue.send(output).is_err() {
+                                break;
+                            }
+                            (item, done)
+                        }
+                    };
+                    if done.send(process(&self, item)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop((queue, finished));
+
+            let outputs: Box<dyn Iterator<Item = FileOutput>> = if self.ordered {
+                Box::new(queued.into_iter().map_while(|output| output.recv().ok()))
+            } else {
+                Box::new(results.into_iter())
+            };
+            let mut last_truthy = None;
+            for (events, truthy) in outputs {
+                last_truthy = truthy.or(last_truthy);
+                for event in events {
+                    match event {
+                        Event::Output(output) => out.write_all(&output)?,
+                        Event::Error(e) => reporter.report(e)?,
+                    }
+                }
+            }
+            Ok(last_truthy)
+        })
+    }
+
+    /// Run the filter over the documents of one file
+    fn run_file(&self, file: &FileArg) -> FileOutput {
+        self.run_docs(read_inputs(self.cli, std::slice::from_ref(file)))
+    }
+
+    /// Run the filter over the documents of one shard (--parallel)
+    fn run_shard(&self, job: Result<ShardJob, OqError>) -> FileOutput {
+        let job = match job {
+            Ok(job) => job,
+            Err(e) => return (vec![Event::Error(e)], None),
+        };
+        let (docs, format) = if self.cli.raw_input {
+            (job.shard.lines(), Format::Json)
+        } else {
+            let options = input_options(self.cli);
+            (job.shard.documents_with(job.format, &options), job.format)
+        };
+        self.run_docs(docs.map(|doc| match (doc, &job.file) {
+            (Ok(value), file) => Ok(InputDoc {
+                value,
+                filename: file.clone(),
+                format,
+            }),
+            (Err(e), Some(file)) => Err(e.with_path(file)),
+            (Err(e), None) => Err(e),
+        }))
+    }
+
+    /// Run the filter over documents, keeping what it prints
+    fn run_docs(&self, docs: impl Iterator<Item = Result<InputDoc, OqError>>) -> FileOutput {
+        let deferred = Deferred::default();
+        let reporter = Reporter::deferred(self.cli.keep_going, &deferred);
+        let printer = Printer::new(self.cli, self.colors);
+        let stream = if self.cli.keep_going {
+            InputStream::keep_going(docs)
+        } else {
+            InputStream::new(docs)
+        };
+
+        let result = run_documents(
+            self.cli,
+            self.filter,
+            self.vars,
+            &stream,
+            &printer,
+            &mut &deferred,
+            &reporter,
+        );
+        let mut events = deferred.0.into_inner();
+        if let Err(e) = result {
+            events.push(Event::Error(e));
         }
+        (events, printer.last_truthy.get())
     }
+}
+
+/// A shard of an input, with the file and format it was read from
+struct ShardJob {
+    shard: Shard,
+    file: Option<String>,
+    format: Format,
+}
+
+/// Bytes at the start of a sharded input that its format is detected from
+const DETECT_SIZE: u64 = 64 << 10;
 
-    // Format based on output format
-    // TOML can only encode objects, fall back to JSON for primitives
-    let effective_format = match format {
-        Format::Toml if !value.is_object() => Format::Json,
-        other => other,
+/// The shards of stdin or of each file in turn
+///
+/// Inputs are read as they are split, rather than all at once.
+fn shards<'a>(
+    cli: &'a Cli,
+    files: &'a [FileArg],
+) -> impl Iterator<Item = Result<ShardJob, OqError>> + Send + 'a {
+    let sources: Vec<Option<&FileArg>> = if files.is_empty() {
+        vec![None]
+    } else {
+        files.iter().map(Some).collect()
     };
 
-    let output = match effective_format {
-        Format::Json => {
-            if compact {
-                serde_json::to_string(value)?
+    sources.into_iter().flat_map(move |file| {
+        let name = file.map_or("<stdin>", |file| file.path.as_str());
+        let named = move |e: io::Error| -> OqError {
+            io::Error::new(e.kind(), format!("{}: {}", name, e)).into()
+        };
+        let shards = open_input(file).and_then(|mut reader| {
+            let mut start = Vec::new();
+            (&mut reader).take(DETECT_SIZE).read_to_end(&mut start)?;
+            let format = if cli.raw_input {
+                Format::Json
+            } else {
+                let text = match std::str::from_utf8(&start) {
+                    Ok(text) => text,
+                    Err(e) => std::str::from_utf8(&start[..e.valid_up_to()]).unwrap_or_default(),
+                };
+                // A start cut in the middle of a document would not parse
+                let whole = (start.len() as u64) < DETECT_SIZE;
+                detect_input_format(cli, text, file, whole)
+            };
+            let reader = io::Cursor::new(start).chain(reader);
+            let shards = if cli.raw_input {
+                Shards::lines(reader)
             } else {
-                serde_json::to_string_pretty(value)?
+                Shards::new(reader, format)
+            };
+            Ok((shards, format))
+        });
+        let jobs: Box<dyn Iterator<Item = Result<ShardJob, OqError>> + Send> = match shards {
+            Ok((shards, format)) => Box::new(shards.map(move |shard| {
+                Ok(ShardJob {
+                    shard: shard.map_err(named)?,
+                    file: file.map(|file| file.path.clone()),
+                    format,
+                })
+            })),
+            Err(e) => Box::new(std::iter::once(Err(named(e)))),
+        };
+        jobs
+    })
+}
+
+/// A reader for a file, or for stdin
+fn open_input(file: Option<&FileArg>) -> io::Result<Box<dyn Read + Send>> {
+    Ok(match file {
+        Some(file) => Box::new(std::fs::File::open(&file.path)?),
+        None => Box::new(io::stdin()),
+    })
+}
+
+/// Expand the file arguments: directories are walked and globs matched
+///
+/// Entries that cannot be read go to the reporter, so that --keep-going walks
+/// past them.
+fn find_files(cli: &Cli, args: &[String], reporter: &Reporter) -> Result<Vec<FileArg>, OqError> {
+    let finder = cli
+        .include
+        .iter()
+        .fold(FileFinder::new(), |finder, glob| finder.with_include(glob));
+    let finder = cli
+        .exclude
+        .iter()
+        .fold(finder, |finder, glob| finder.with_exclude(glob))
+        .with_no_ignore(cli.no_ignore);
+
+    let mut files = Vec::new();
+    for arg in args {
+        let file = FileArg::parse(arg);
+        for path in finder.find_with(&file.path, |e| reporter.report(e))? {
+            files.push(FileArg {
+                path: path.to_string_lossy().into_owned(),
+                format: file.format,
+            });
+        }
+    }
+    Ok(files)
+}
+
+/// Attach the file of the current input to an error
+fn in_file<T>(result: Result<T, OqError>, stream: &InputStream) -> Result<T, OqError> {
+    result.map_err(|e| match stream.filename() {
+        Some(file) => e.with_path(file),
+        None => e,
+    })
+}
+
+/// Build filter variables from --arg, --argjson, --slurpfile, --rawfile and --args
+fn filter_vars(cli: &Cli, args: &[String]) -> Result<FilterVars, OqError> {
+    let mut vars = FilterVars::new();
+
+    for pair in cli.named_args.chunks(2) {
+        vars = vars.with_var(&pair[0], serde_json::Value::String(pair[1].clone()));
+    }
+    for pair in cli.named_json_args.chunks(2) {
+        let value = serde_json::from_str(&pair[1])
+            .map_err(|e| OqError::Usage(format!("invalid JSON text passed to --argjson: {}", e)))?;
+        vars = vars.with_var(&pair[0], value);
+    }
+    for pair in cli.slurp_files.chunks(2) {
+        let file = FileArg::parse(&pair[1]);
+        let input = read_file(&file.path)?;
+        // Read like the same file given as an input
+        let (docs, _) = input_documents(cli, &input, Some(&file));
+        let docs = docs
+            .collect::<Result<Val, _>>()
+            .and_then(|docs| val_to_json(&docs))
+            .map_err(|e| e.with_path(&file.path))?;
+        vars = vars.with_var(&pair[0], docs);
+    }
+    for pair in cli.raw_files.chunks(2) {
+        let input = read_file(&pair[1])?;
+        vars = vars.with_var(&pair[0], serde_json::Value::String(input));
+    }
+
+    if cli.string_args {
+        for arg in args {
+            vars = vars.with_positional(serde_json::Value::String(arg.clone()));
+        }
+    } else if cli.json_args {
+        for arg in args {
+            let value = serde_json::from_str(arg).map_err(|e| {
+                OqError::Usage(format!("invalid JSON text passed to --jsonargs: {}", e))
+            })?;
+            vars = vars.with_positional(value);
+        }
+    }
+
+    Ok(vars)
+}
+
+/// An input file argument, optionally prefixed with its format (`yaml:path`)
+struct FileArg {
+    path: String,
+    format: Option<Format>,
+}
+
+impl FileArg {
+    fn parse(arg: &str) -> Self {
+        // A file that exists under the full name wins over the prefix syntax
+        if let Some((prefix, path)) = arg.split_once(':') {
+            if let Some(format) = Format::from_name(prefix) {
+                if !Path::new(arg).exists() {
+                    return Self {
+                        path: path.to_string(),
+                        format: Some(format),
+                    };
+                }
             }
         }
-        _ => encode_to_format(value, effective_format)?,
+        Self {
+            path: arg.to_string(),
+            format: None,
+        }
+    }
+}
+
+/// Format of an input: FORMAT: prefix > -i > extension > content
+///
+/// `input` is the whole text, or only its start if `whole` is false. With
+/// --explain-detect, the choice is reported on stderr.
+fn detect_input_format(cli: &Cli, input: &str, file: Option<&FileArg>, whole: bool) -> Format {
+    let name = file.map_or("<stdin>", |file| file.path.as_str());
+    let explicit = match cli.input_format.unwrap_or_default() {
+        InputFormat::Auto => None,
+        explicit => Some(explicit.detect(input)),
+    };
+    let (format, reason) = if let Some(format) = file.and_then(|file| file.format) {
+        (format, format!("from the {}: prefix", format.name()))
+    } else if let Some(format) = explicit {
+        (format, "from --input".to_string())
+    } else if let Some(format) =
+        file.and_then(|file| Format::from_path_and_input(&file.path, input))
+    {
+        (format, "from the file extension".to_string())
+    } else {
+        let detection = if whole {
+            explain_format(input)
+        } else {
+            explain_format_start(input)
+        };
+        if cli.explain_detect {
+            eprintln!("oq: {}: {}", name, detection);
+        }
+        return detection.format;
     };
+    if cli.explain_detect {
+        eprintln!("oq: {}: {} ({})", name, format.name(), reason);
+    }
+    format
+}
 
-    writeln!(out, "{}", output)?;
-    Ok(())
+/// The documents of an input, with the format they are read as
+type Documents<'a> = (ValDocuments<'a>, Format);
+
+self_cell! {
+    /// The text of an input along with the documents parsed lazily from it
+    struct InputText {
+        owner: String,
+        #[not_covariant]
+        dependent: Documents,
+    }
+}
+
+/// Documents from stdin or from each file in turn
+///
+/// A file is only read once the stream reaches it, and its text is dropped
+/// once its last document has been read.
+fn read_inputs<'a>(
+    cli: &'a Cli,
+    files: &'a [FileArg],
+) -> impl Iterator<Item = Result<InputDoc, OqError>> + 'a {
+    let sources: Vec<Option<&FileArg>> = if files.is_empty() {
+        vec![None]
+    } else {
+        files.iter().map(Some).collect()
+    };
+
+    sources.into_iter().flat_map(move |file| {
+        let text = match file {
+            Some(file) => read_file(&file.path),
+            None => read_stdin(),
+        };
+        let docs: Box<dyn Iterator<Item = Result<InputDoc, OqError>>> = match text {
+            Ok(text) => {
+                let mut input = InputText::new(text, |text| input_documents(cli, text, file));
+                let format = input.with_dependent(|_, (_, format)| *format);
+                let docs = std::iter::from_fn(move || {
+                    input.with_dependent_mut(|_, (docs, _)| docs.next())
+                });
+                Box::new(docs.map(move |doc| match (doc, file) {
+                    (Ok(value), _) => Ok(InputDoc {
+                        value,
+                        filename: file.map(|file| file.path.clone()),
+                        format,
+                    }),
+                    (Err(e), Some(file)) => Err(e.with_path(&file.path)),
+                    (Err(e), None) => Err(e),
+                }))
+            }
+            Err(e) => Box::new(std::iter::once(Err(e.into()))),
+        };
+        docs
+    })
+}
+
+/// The text of stdin or of all files, concatenated
+fn read_all(files: &[FileArg]) -> Result<String, OqError> {
+    if files.is_empty() {
+        return Ok(read_stdin()?);
+    }
+    let mut text = String::new();
+    for file in files {
+        text.push_str(&read_file(&file.path)?);
+    }
+    Ok(text)
+}
+
+/// Split an input into documents, along with the format they were read as
+///
+/// Raw input (-R) yields one string per line and counts as JSON for output.
+fn input_documents<'a>(
+    cli: &Cli,
+    input: &'a str,
+    file: Option<&FileArg>,
+) -> (ValDocuments<'a>, Format) {
+    if cli.raw_input {
+        return (parse_raw_lines(input), Format::Json);
+    }
+    let input_fmt = detect_input_format(cli, input, file, true);
+    let options = input_options(cli);
+    (parse_stream_val_with(input, input_fmt, &options), input_fmt)
+}
+
+/// Options for parsing CSV and TSV inputs
+fn input_options(cli: &Cli) -> InputOptions {
+    InputOptions::new()
+        .with_header(!cli.no_header)
+        .with_infer_types(cli.infer_types)
+}
+
+/// The format shared by all inputs, or JSON when they are mixed
+fn common_format(formats: &[Format]) -> Format {
+    match formats.split_first() {
+        Some((first, rest)) if rest.iter().all(|f| f == first) => output_for(*first),
+        _ => Format::Json,
+    }
+}
+
+/// The output format for an input format when none is given: JSON5 and
+/// JSONC inputs are written as plain JSON
+fn output_for(format: Format) -> Format {
+    match format {
+        Format::Json5 => Format::Json,
+        format => format,
+    }
+}
+
+/// Writes filter results in the requested format
+struct Printer<'a> {
+    options: OutputOptions,
+    /// Write strings without quotes
+    raw: bool,
+    /// Written after each result: a newline, nothing (-j) or NUL (--raw-output0)
+    separator: &'static [u8],
+    colors: Option<&'a ColorScheme>,
+    /// Print the file of each result (-H)
+    with_filename: bool,
+    /// CSV and TSV columns, set by the first object printed so that later
+    /// objects are rows of the same table
+    columns: RefCell<Option<Vec<String>>>,
+    header_written: Cell<bool>,
+    /// Whether the last printed value was truthy (neither false nor null)
+    last_truthy: Cell<Option<bool>>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(cli: &Cli, colors: Option<&'a ColorScheme>) -> Self {
+        let indent = match cli.indent {
+            _ if cli.tab => Indent::Tab,
+            Some(n) => Indent::Spaces(n.into()),
+            None => Indent::Spaces(2),
+        };
+        let separator: &[u8] = if cli.raw_output0 {
+            b"\0"
+        } else if cli.join_output {
+            b""
+        } else {
+            b"\n"
+        };
+        let mut options = OutputOptions::new()
+            .with_indent(indent)
+            .with_compact(cli.compact)
+            .with_sort_keys(cli.sort_keys)
+            .with_ascii(cli.ascii_output)
+            .with_header(!cli.no_header)
+            .with_export(cli.export)
+            .with_env_separator(cli.env_separator.as_str());
+        if let Some(columns) = &cli.columns {
+            options = options.with_columns(columns.clone());
+        }
+        Self {
+            options,
+            // Like jq, -a prints strings as escaped JSON even with -r
+            raw: (cli.raw || cli.join_output || cli.raw_output0) && !cli.ascii_output,
+            separator,
+            colors,
+            with_filename: cli.with_filename,
+            columns: RefCell::new(cli.columns.clone()),
+            header_written: Cell::new(false),
+            last_truthy: Cell::new(None),
+        }
+    }
+
+    /// Print each result of a filter run over the current input of `stream`
+    fn print_results(
+        &self,
+        out: &mut impl Write,
+        results: ValResults,
+        format: Format,
+        stream: &InputStream,
+        reporter: &Reporter,
+    ) -> Result<(), OqError> {
+        for value in results {
+            let printed = value.and_then(|value| {
+                let file = self.with_filename.then(|| stream.filename());
+                self.print(out, &value, format, file.as_ref().map(Option::as_deref))
+            });
+            if let Err(e) = in_file(printed, stream) {
+                reporter.report(e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print a result; with -H, `file` is `Some` with the path of its input
+    /// (`None` for stdin)
+    fn print(
+        &self,
+        out: &mut impl Write,
+        value: &Val,
+        format: Format,
+        file: Option<Option<&str>>,
+    ) -> Result<(), OqError> {
+        self.last_truthy
+            .set(Some(!matches!(value, Val::Null | Val::Bool(false))));
+
+        // Text output gets a `FILE:` prefix, structured output a wrapper object
+        let wrapped;
+        let value = match file {
+            Some(file) if self.raw => {
+                write!(out, "{}:", file.unwrap_or("<stdin>"))?;
+                value
+            }
+            Some(file) => {
+                let file = file.map_or(Val::Null, |file| Val::from(file.to_string()));
+                let entries = [
+                    (Val::from("file".to_string()), file),
+                    (Val::from("value".to_string()), value.clone()),
+                ];
+                wrapped = Val::obj(entries.into_iter().collect());
+                &wrapped
+            }
+            None => value,
+        };
+
+        // Raw string output (like jq -r)
+        if self.raw {
+            if let Val::Str(s, _) = value {
+                if self.separator == b"\0" && s.contains(&0) {
+                    return Err(FilterError::new(
+                        "Cannot dump a string containing NUL with --raw-output0",
+                    )
+                    .into());
+                }
+                out.write_all(s)?;
+                out.write_all(self.separator)?;
+                return Ok(());
+            }
+        }
+
+        // Format based on output format
+        // TOML and INI can only encode objects, fall back to JSON for primitives.
+        // Env output is an error instead, as it is meant for `eval`.
+        let effective_format = match format {
+            Format::Toml | Format::Ini if !matches!(value, Val::Obj(_)) => Format::Json,
+            other => other,
+        };
+
+        let output = match (effective_format, value) {
+            (Format::Csv | Format::Tsv, Val::Obj(obj)) => {
+                // Later objects are rows under the header of the first one
+                let mut columns = self.columns.borrow_mut();
+                let columns = columns.get_or_insert_with(|| obj.keys().map(key_string).collect());
+                let header = self.options.header && !self.header_written.replace(true);
+                let options = self
+                    .options
+                    .clone()
+                    .with_header(header)
+                    .with_columns(columns.clone());
+                encode_val_to_format_with(value, effective_format, &options)?
+            }
+            _ => encode_val_to_format_with(value, effective_format, &self.options)?,
+        };
+        // YAML and TOML end their documents with a newline; the separator adds it
+        let output = output.strip_suffix('\n').unwrap_or(&output);
+
+        match self.colors {
+            Some(scheme) => write!(out, "{}", highlight(output, effective_format, scheme))?,
+            None => write!(out, "{}", output)?,
+        }
+        out.write_all(self.separator)?;
+        Ok(())
+    }
+}
+
+/// Read a file, naming it in the error like jq does
+///
+/// With --keep-going several inputs can fail, so each error has to say
+/// which file it is about.
+fn read_file(path: &str) -> io::Result<String> {
+    std::fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
 }
 
 fn read_stdin() -> Result<String, io::Error> {
@@ -210,6 +1328,8 @@ fn read_stdin() -> Result<String, io::Error> {
     Ok(input)
 }
 
-fn is_terminal() -> bool {
-    std::env::var("TERM").is_ok()
+/// Whether `--color auto` should colorize: stdout is a terminal and
+/// NO_COLOR is not set
+fn use_color() -> bool {
+    io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
 }
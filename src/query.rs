@@ -1,60 +1,542 @@
 //! jq-compatible query engine using jaq
 
+use crate::convert::stop_after_error;
+use crate::diagnostic::{CompileError, Location};
+use crate::input::InputStream;
+use crate::locate::{locate_error, Stage};
+use crate::val::{json_to_val, val_to_json};
 use crate::OqError;
+use jaq_core::data::HasLut;
+use jaq_core::{DataT, Error, Exn, Lut, Native, RunPtr, ValX};
+use jaq_json::Val;
 use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+/// Iterator over the outputs of a filter
+pub type Results<'a> = Box<dyn Iterator<Item = Result<Value, OqError>> + 'a>;
+
+/// Iterator over the outputs of a filter, as jaq values
+pub type ValResults<'a> = Box<dyn Iterator<Item = Result<Val, OqError>> + 'a>;
 
 /// A compiled jq filter
 pub struct CompiledFilter {
-    filter: jaq_core::Filter<jaq_core::data::JustLut<jaq_json::Val>>,
+    filter: jaq_core::Filter<OqData>,
+    /// Global variables the filter was compiled with, in binding order
+    vars: Vec<String>,
+    /// Source of the filter, to locate runtime errors in it
+    source: FilterSource,
+    /// Stages of the filter's pipeline, compiled on its first runtime error
+    pub(crate) stages: OnceLock<Option<Vec<Stage>>>,
+}
+
+impl CompiledFilter {
+    pub(crate) fn source(&self) -> &FilterSource {
+        &self.source
+    }
+
+    /// Whether the filter may take documents with `input` or `inputs`
+    ///
+    /// Such a filter has to see the inputs in order, one run after the
+    /// other. Filters that import modules are assumed to.
+    pub fn reads_inputs(&self) -> bool {
+        words(&self.source.code)
+            .into_iter()
+            .any(|word| matches!(word, "input" | "inputs" | "import" | "include"))
+    }
+
+    /// Global variables, in binding order
+    pub(crate) fn var_names(&self) -> &[String] {
+        &self.vars
+    }
+}
+
+/// Where a filter was compiled from
+#[derive(Clone)]
+pub(crate) struct FilterSource {
+    pub(crate) code: String,
+    pub(crate) path: PathBuf,
+    pub(crate) search_paths: Vec<PathBuf>,
+}
+
+/// Data types of oq filters: jaq values, with global data that gives native
+/// filters access to the input stream
+struct OqData;
+
+impl DataT for OqData {
+    type V<'a> = Val;
+    type Data<'a> = Rc<Globals<'a>>;
+}
+
+/// Global data available to native filters while a filter runs
+struct Globals<'a> {
+    lut: &'a Lut<OqData>,
+    inputs: Option<&'a (dyn InputSource + 'a)>,
+}
+
+impl<'a> HasLut<'a, OqData> for Rc<Globals<'a>> {
+    fn lut(&self) -> &'a Lut<OqData> {
+        self.lut
+    }
+}
+
+impl Globals<'_> {
+    fn next_input(&self) -> Option<ValX<Val>> {
+        let input = self.inputs?.next_value()?;
+        Some(input.map_err(|e| Exn::from(Error::str(e))))
+    }
+
+    fn input_filename(&self) -> Val {
+        match self.inputs.and_then(|inputs| inputs.filename()) {
+            Some(name) => Val::from(name),
+            None => Val::Null,
+        }
+    }
+}
+
+/// Object-safe view of an [`InputStream`] of any lifetime
+pub(crate) trait InputSource {
+    fn next_value(&self) -> Option<Result<Val, String>>;
+    fn filename(&self) -> Option<String>;
+}
+
+impl InputSource for InputStream<'_> {
+    fn next_value(&self) -> Option<Result<Val, String>> {
+        InputStream::next_value(self)
+    }
+
+    fn filename(&self) -> Option<String> {
+        InputStream::filename(self)
+    }
+}
+
+/// The `input`, `inputs` and `input_filename` filters
+fn input_funs() -> impl Iterator<Item = jaq_std::Filter<Native<OqData>>> {
+    let funs: [jaq_std::Filter<RunPtr<OqData>>; 3] = [
+        ("inputs", jaq_std::v(0), |cv| {
+            let globals = cv.0.data().clone();
+            Box::new(std::iter::from_fn(move || globals.next_input()))
+        }),
+        ("input", jaq_std::v(0), |cv| {
+            let input = cv.0.data().next_input();
+            let input = input.unwrap_or_else(|| Err(Exn::from(Error::str("No more inputs"))));
+            Box::new(std::iter::once(input))
+        }),
+        ("input_filename", jaq_std::v(0), |cv| {
+            Box::new(std::iter::once(Ok(cv.0.data().input_filename())))
+        }),
+    ];
+    funs.into_iter().map(jaq_std::run::<OqData>)
+}
+
+/// Variables made available to a filter (like jq `--arg` / `--args`)
+///
+/// Named variables are bound as `$name`. Both named and positional values are
+/// also exposed through `$ARGS.named` and `$ARGS.positional`.
+#[derive(Debug, Clone, Default)]
+pub struct FilterVars {
+    named: serde_json::Map<String, Value>,
+    positional: Vec<Value>,
+}
+
+impl FilterVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind `$name` to a value (a later binding of the same name wins)
+    pub fn with_var(mut self, name: &str, value: Value) -> Self {
+        self.named
+            .insert(name.trim_start_matches('$').to_string(), value);
+        self
+    }
+
+    /// Append a positional value to `$ARGS.positional`
+    pub fn with_positional(mut self, value: Value) -> Self {
+        self.positional.push(value);
+        self
+    }
+
+    /// Get the value of a named variable
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.named.get(name.trim_start_matches('$'))
+    }
+
+    /// The `$ARGS` object: `{"positional": [...], "named": {...}}`
+    pub fn args(&self) -> Value {
+        serde_json::json!({
+            "positional": self.positional,
+            "named": self.named,
+        })
+    }
 }
 
 /// Compile a jq filter expression
 pub fn compile_filter(code: &str) -> Result<CompiledFilter, OqError> {
+    compile_filter_with_vars(code, &FilterVars::default())
+}
+
+/// Compile a jq filter expression that may reference the given variables
+///
+/// `$ARGS` is always defined. Values are bound when running the filter
+/// with [`run_filter_with_vars`]. Modules are looked up in
+/// [`default_search_paths`].
+pub fn compile_filter_with_vars(code: &str, vars: &FilterVars) -> Result<CompiledFilter, OqError> {
+    compile_filter_with_paths(code, vars, &default_search_paths())
+}
+
+/// Compile a jq filter expression, resolving `import`/`include` in the given
+/// directories
+pub fn compile_filter_with_paths(
+    code: &str,
+    vars: &FilterVars,
+    search_paths: &[PathBuf],
+) -> Result<CompiledFilter, OqError> {
+    compile(code, PathBuf::new(), vars, search_paths)
+}
+
+/// Compile the jq program in a file (like jq `-f`)
+///
+/// Modules are resolved in `search_paths`, and `search` paths given in module
+/// metadata are relative to the file that contains them.
+pub fn compile_filter_from_path(
+    path: impl AsRef<Path>,
+    vars: &FilterVars,
+    search_paths: &[PathBuf],
+) -> Result<CompiledFilter, OqError> {
+    let path = path.as_ref();
+    let code = std::fs::read_to_string(path)?;
+    compile(&code, path.to_path_buf(), vars, search_paths)
+}
+
+/// Module search paths used when none are given: jq's defaults
+///
+/// `~` is the home directory and `$ORIGIN` the directory of the executable.
+pub fn default_search_paths() -> Vec<PathBuf> {
+    ["~/.jq", "$ORIGIN/../lib/jq", "$ORIGIN/../lib"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn compile(
+    code: &str,
+    path: PathBuf,
+    vars: &FilterVars,
+    search_paths: &[PathBuf],
+) -> Result<CompiledFilter, OqError> {
+    let names: Vec<String> = std::iter::once("$ARGS".to_string())
+        .chain(
+            vars.named
+                .keys()
+                .filter(|name| *name != "ARGS")
+                .map(|name| format!("${}", name)),
+        )
+        .collect();
+    let source = FilterSource {
+        code: code.to_string(),
+        path,
+        search_paths: search_paths.to_vec(),
+    };
+    compile_source(source, names)
+}
+
+/// Compile a filter with the given global variables, in binding order
+pub(crate) fn compile_source(
+    source: FilterSource,
+    names: Vec<String>,
+) -> Result<CompiledFilter, OqError> {
     use jaq_core::load::{Arena, File, Loader};
 
+    let (code, path, search_paths) = (&source.code, &source.path, &source.search_paths);
     let arena = Arena::default();
-    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs())).with_std_read(search_paths);
 
     let modules = loader
-        .load(&arena, File { path: (), code })
-        .map_err(|e| OqError::Compile(format!("{:?}", e)))?;
+        .load(
+            &arena,
+            File {
+                path: path.clone(),
+                code: code.as_str(),
+            },
+        )
+        .map_err(load_errors)?;
+
+    // Data imports (`import "file" as $name;`) would need their values bound
+    // at run time, which oq does not do
+    jaq_core::load::import(&modules, |_| {
+        Err("data imports are not supported".to_string())
+    })
+    .map_err(load_errors)?;
 
     let filter = jaq_core::Compiler::default()
-        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()).chain(input_funs()))
+        .with_global_vars(names.iter().map(String::as_str))
         .compile(modules)
-        .map_err(|e| OqError::Compile(format!("{:?}", e)))?;
+        .map_err(|errors| compile_errors(errors, &names))?;
 
-    Ok(CompiledFilter { filter })
+    Ok(CompiledFilter {
+        filter,
+        vars: names,
+        source,
+        stages: OnceLock::new(),
+    })
 }
 
-/// Run a compiled filter on a JSON value
-pub fn run_filter(filter: &CompiledFilter, input: Value) -> Result<Vec<Value>, OqError> {
-    use jaq_core::{unwrap_valr, Ctx, Vars};
+/// Turn jaq's lex, parse and module loading errors into [`CompileError`]s
+fn load_errors(errors: jaq_core::load::Errors<&str, PathBuf>) -> OqError {
+    use jaq_core::load::Error;
+
+    let mut out = Vec::new();
+    for (file, error) in errors {
+        let at = |part: &str, message: String| located(&file, part, message);
+        match error {
+            Error::Io(errors) => out.extend(
+                errors
+                    .into_iter()
+                    .map(|(path, e)| at(path, format!("could not load `{}`: {}", path, e))),
+            ),
+            Error::Lex(errors) => out.extend(errors.into_iter().map(|(expect, found)| {
+                // The lexer reports the rest of the input; point at its start
+                let found = found.get(..1).unwrap_or(found);
+                at(found, format!("expected {}", expect.as_str()))
+            })),
+            Error::Parse(errors) => out.extend(errors.into_iter().map(|(expect, found)| {
+                let message = if found.is_empty() {
+                    format!("expected {}, found end of input", expect.as_str())
+                } else {
+                    format!("expected {}, found `{}`", expect.as_str(), found)
+                };
+                at(found, message)
+            })),
+        }
+    }
+    OqError::Compile(out)
+}
 
-    // Convert serde_json::Value to jaq_json::Val
-    let input: jaq_json::Val = input.into();
-    let mut results = Vec::new();
+/// Turn undefined names into [`CompileError`]s, suggesting close matches
+fn compile_errors(errors: jaq_core::compile::Errors<&str, PathBuf>, vars: &[String]) -> OqError {
+    use jaq_core::compile::Undefined;
 
-    // Create context for filter execution
-    let ctx = Ctx::<jaq_core::data::JustLut<jaq_json::Val>>::new(&filter.filter.lut, Vars::new([]));
-
-    // Run the filter
-    for output in filter.filter.id.run((ctx.clone(), input)) {
-        match unwrap_valr(output) {
-            Ok(val) => {
-                // Convert jaq_json::Val back to serde_json::Value
-                let json_val: Value = (&val)
-                    .try_into()
-                    .map_err(|_| OqError::Filter("Failed to convert output to JSON".to_string()))?;
-                results.push(json_val);
-            }
-            Err(e) => {
-                return Err(OqError::Filter(format!("{}", e)));
+    let mut out = Vec::new();
+    for (file, errors) in errors {
+        for (name, undefined) in errors {
+            let (message, candidates) = match undefined {
+                Undefined::Filter(arity) => (
+                    format!("undefined filter `{}/{}`", name, arity),
+                    filter_names(file.code),
+                ),
+                Undefined::Var => (
+                    format!("undefined variable `{}`", name),
+                    var_names(file.code, vars),
+                ),
+                undefined => (
+                    format!("undefined {} `{}`", undefined.as_str(), name),
+                    Vec::new(),
+                ),
+            };
+            let error = located(&file, name, message);
+            out.push(match closest_match(name, &candidates) {
+                Some(suggestion) => error.with_suggestion(suggestion),
+                None => error,
+            });
+        }
+    }
+    OqError::Compile(out)
+}
+
+/// A compile error pointing at `part` of the file's code, when it is a
+/// slice of it
+fn located(
+    file: &jaq_core::load::File<&str, PathBuf>,
+    part: &str,
+    message: String,
+) -> CompileError {
+    let code = file.code;
+    let start = (part.as_ptr() as usize).wrapping_sub(code.as_ptr() as usize);
+    let mut error = CompileError::new(message);
+    if start <= code.len() && start + part.len() <= code.len() {
+        error = error.with_location(Location::from_span(code, start..start + part.len()));
+    }
+    if !file.path.as_os_str().is_empty() {
+        error = error.with_path(file.path.display().to_string());
+    }
+    error
+}
+
+/// Names of the filters available to a filter: builtins and its own
+/// definitions
+fn filter_names(code: &str) -> Vec<String> {
+    let natives = jaq_std::funs::<OqData>()
+        .chain(jaq_json::funs())
+        .chain(input_funs())
+        .map(|(name, _, _)| name.to_string());
+    let defs = jaq_std::defs()
+        .chain(jaq_json::defs())
+        .map(|def| def.name.to_string());
+    let words = words(code);
+    let own = words
+        .windows(2)
+        .filter(|pair| pair[0] == "def")
+        .map(|pair| pair[1].to_string());
+    natives.chain(defs).chain(own).collect()
+}
+
+/// Names of the variables available to a filter: globals and the ones it
+/// binds itself
+fn var_names(code: &str, vars: &[String]) -> Vec<String> {
+    let own = words(code)
+        .into_iter()
+        .filter(|word| word.starts_with('$'))
+        .map(str::to_string);
+    vars.iter().cloned().chain(own).collect()
+}
+
+/// Identifiers and `$variables` in jq code
+fn words(code: &str) -> Vec<&str> {
+    code.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// The candidate closest to `name`, if it is close enough to be a typo
+fn closest_match(name: &str, candidates: &[String]) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .filter(|candidate| candidate.as_str() != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// Edit distance counting insertions, deletions, substitutions and swaps of
+/// adjacent characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
             }
         }
     }
+    d[a.len()][b.len()]
+}
+
+/// Run a compiled filter on a JSON value
+pub fn run_filter(filter: &CompiledFilter, input: Value) -> Result<Vec<Value>, OqError> {
+    run_filter_with_vars(filter, input, &FilterVars::default())
+}
+
+/// Run a compiled filter on a JSON value, binding the given variables
+///
+/// Variables the filter was compiled with but which are missing from `vars`
+/// are bound to `null`.
+pub fn run_filter_with_vars(
+    filter: &CompiledFilter,
+    input: Value,
+    vars: &FilterVars,
+) -> Result<Vec<Value>, OqError> {
+    run_filter_iter(filter, input, vars).collect()
+}
+
+/// Lazily run a compiled filter, yielding each output as soon as it is produced
+///
+/// Outputs that are never pulled from the iterator are never computed, so
+/// `limit`-style consumers only pay for what they use. The iterator ends
+/// after the first error.
+pub fn run_filter_iter<'a>(
+    filter: &'a CompiledFilter,
+    input: Value,
+    vars: &FilterVars,
+) -> Results<'a> {
+    let outputs = run_filter_val_iter(filter, json_to_val(input), vars);
+    Box::new(outputs.map(|output| output.and_then(|val| val_to_json(&val))))
+}
+
+/// Lazily run a compiled filter on a jaq value, without converting through JSON
+///
+/// The filter has no inputs: `inputs` yields nothing, and `input` fails.
+pub fn run_filter_val_iter<'a>(
+    filter: &'a CompiledFilter,
+    input: Val,
+    vars: &FilterVars,
+) -> ValResults<'a> {
+    run(filter, input, vars, None)
+}
+
+/// Lazily run a compiled filter with access to a shared input stream
+///
+/// `input` and `inputs` take documents from `inputs`, and `input_filename`
+/// reports the file of the document taken last. An error in the stream is
+/// returned as-is rather than as a filter error.
+pub fn run_filter_with_inputs<'a>(
+    filter: &'a CompiledFilter,
+    input: Val,
+    vars: &FilterVars,
+    inputs: &'a InputStream<'_>,
+) -> ValResults<'a> {
+    let outputs = run(filter, input, vars, Some(inputs));
+    Box::new(outputs.map(|output| output.map_err(|e| inputs.take_error().unwrap_or(e))))
+}
 
-    Ok(results)
+fn run<'a>(
+    filter: &'a CompiledFilter,
+    input: Val,
+    vars: &FilterVars,
+    inputs: Option<&'a (dyn InputSource + 'a)>,
+) -> ValResults<'a> {
+    // Bind global variables in the order they were declared at compile time
+    let values: Vec<Val> = filter
+        .vars
+        .iter()
+        .map(|name| match name.as_str() {
+            "$ARGS" => json_to_val(vars.args()),
+            name => json_to_val(vars.get(name).cloned().unwrap_or(Value::Null)),
+        })
+        .collect();
+
+    let outputs = run_raw(filter, input.clone(), values.clone(), inputs).map(move |output| {
+        output.map_err(|message| locate_error(filter, &input, &values, inputs, message).into())
+    });
+    stop_after_error(outputs)
+}
+
+/// Run a filter with its global variables bound to `values`, reporting errors
+/// as jaq's message only
+pub(crate) fn run_raw<'a>(
+    filter: &'a CompiledFilter,
+    input: Val,
+    values: Vec<Val>,
+    inputs: Option<&'a (dyn InputSource + 'a)>,
+) -> impl Iterator<Item = Result<Val, String>> + 'a {
+    use jaq_core::{unwrap_valr, Ctx, Vars};
+
+    // Create context for filter execution
+    let globals = Rc::new(Globals {
+        lut: &filter.filter.lut,
+        inputs,
+    });
+    let ctx = Ctx::<OqData>::new(globals, Vars::new(values));
+
+    filter
+        .filter
+        .id
+        .run((ctx, input))
+        .map(|output| unwrap_valr(output).map_err(|e| e.to_string()))
 }
 
 #[cfg(test)]
@@ -62,6 +544,13 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn test_compiled_filter_is_send_sync() {
+        fn send_sync<T: Send + Sync>(_: &T) {}
+        send_sync(&compile_filter(".").unwrap());
+        send_sync(&Val::Null);
+    }
+
     #[test]
     fn test_identity_filter() {
         let filter = compile_filter(".").unwrap();
@@ -148,4 +637,188 @@ mod tests {
         let results = run_filter(&filter, input).unwrap();
         assert_eq!(results, vec![json!("Ada")]);
     }
+
+    #[test]
+    fn test_run_filter_iter_is_lazy() {
+        // `repeat` never terminates, so this only works if outputs are pulled on demand
+        let filter = compile_filter("repeat(.)").unwrap();
+        let mut results = run_filter_iter(&filter, json!(1), &FilterVars::default());
+        assert_eq!(results.next().unwrap().unwrap(), json!(1));
+        assert_eq!(results.next().unwrap().unwrap(), json!(1));
+    }
+
+    #[test]
+    fn test_run_filter_iter_stops_after_error() {
+        let filter = compile_filter(r#"1, error("boom"), 2"#).unwrap();
+        let results: Vec<_> =
+            run_filter_iter(&filter, Value::Null, &FilterVars::default()).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_named_vars() {
+        let vars = FilterVars::new()
+            .with_var("name", json!("Ada"))
+            .with_var("$age", json!(36));
+        let filter = compile_filter_with_vars("{name: $name, age: $age}", &vars).unwrap();
+        let results = run_filter_with_vars(&filter, Value::Null, &vars).unwrap();
+        assert_eq!(results, vec![json!({"name": "Ada", "age": 36})]);
+    }
+
+    #[test]
+    fn test_args_object() {
+        let vars = FilterVars::new()
+            .with_var("a", json!(1))
+            .with_positional(json!("x"))
+            .with_positional(json!(2));
+        let filter = compile_filter_with_vars("$ARGS", &vars).unwrap();
+        let results = run_filter_with_vars(&filter, Value::Null, &vars).unwrap();
+        assert_eq!(
+            results,
+            vec![json!({"positional": ["x", 2], "named": {"a": 1}})]
+        );
+    }
+
+    #[test]
+    fn test_undefined_var_fails_to_compile() {
+        assert!(compile_filter("$missing").is_err());
+        assert!(compile_filter("$ARGS.named").is_ok());
+    }
+
+    fn compile_errors(code: &str) -> Vec<CompileError> {
+        match compile_filter(code) {
+            Err(OqError::Compile(errors)) => errors,
+            Err(e) => panic!("expected a compile error, got {:?}", e),
+            Ok(_) => panic!("expected a compile error"),
+        }
+    }
+
+    #[test]
+    fn test_compile_error_suggests_close_match() {
+        let errors = compile_errors(".items | lenght");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "undefined filter `lenght/0`");
+        assert_eq!(errors[0].suggestion.as_deref(), Some("length"));
+        assert_eq!(errors[0].location.as_ref().unwrap().span, 9..15);
+
+        let errors = compile_errors(".a as $name | $nmae");
+        assert_eq!(errors[0].suggestion.as_deref(), Some("$name"));
+        assert_eq!(compile_errors("frobnicate")[0].suggestion, None);
+    }
+
+    #[test]
+    fn test_compile_error_points_at_syntax_error() {
+        let errors = compile_errors(".foo |");
+        assert_eq!(errors[0].message, "expected term, found end of input");
+        let location = errors[0].location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (1, 7));
+        assert_eq!(errors[0].path, None);
+    }
+
+    #[test]
+    fn test_filter_error_locates_value() {
+        let filter = compile_filter(".items[] | .spec | .replicas + 1").unwrap();
+        let input = json!({"items": [{"spec": {"replicas": 1}}, {"spec": {"replicas": "3"}}]});
+        let error = match run_filter(&filter, input) {
+            Err(OqError::Filter(error)) => error,
+            other => panic!("expected a filter error, got {:?}", other),
+        };
+        assert_eq!(error.path_string().as_deref(), Some(".items[1].spec"));
+        assert_eq!(error.value.as_deref(), Some(r#"{"replicas":"3"}"#));
+        assert_eq!(error.expression.as_deref(), Some(".replicas + 1"));
+    }
+
+    #[test]
+    fn test_filter_error_without_path() {
+        // map(...) is not a path expression, so the path is lost after it
+        let filter = compile_filter("map(.a) | .[] | .b").unwrap();
+        let error = match run_filter(&filter, json!([{"a": 1}])) {
+            Err(OqError::Filter(error)) => error,
+            other => panic!("expected a filter error, got {:?}", other),
+        };
+        assert_eq!(error.path, None);
+        assert_eq!(error.value.as_deref(), Some("1"));
+        assert_eq!(error.expression.as_deref(), Some(".b"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("lenght", "length"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_import_from_search_path() {
+        let lib = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jq"));
+        let filter = compile_filter_with_paths(
+            r#"import "util" as u; u::names"#,
+            &FilterVars::new(),
+            &[lib],
+        )
+        .unwrap();
+        let results = run_filter(&filter, json!([{"name": "a"}, {"name": "b"}])).unwrap();
+        assert_eq!(results, vec![json!(["a", "b"])]);
+
+        assert!(
+            compile_filter_with_paths(r#"import "util" as u; 1"#, &FilterVars::new(), &[]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_run_filter_with_inputs() {
+        use crate::{InputDoc, InputStream};
+
+        let docs = [1, 2, 3].map(|n| {
+            Ok(InputDoc {
+                value: Val::from(n as isize),
+                filename: Some(format!("{}.json", n)),
+                format: crate::Format::Json,
+            })
+        });
+        let stream = InputStream::new(docs.into_iter());
+        let first = stream.next_doc().unwrap().unwrap();
+        let filter = compile_filter("[., [inputs], input_filename]").unwrap();
+        let results: Vec<Val> =
+            run_filter_with_inputs(&filter, first.value, &FilterVars::new(), &stream)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(
+            val_to_json(&results[0]).unwrap(),
+            json!([1, [2, 3], "3.json"])
+        );
+
+        // Without inputs, `input` fails and `inputs` is empty
+        let filter = compile_filter("[inputs]").unwrap();
+        let results: Vec<_> = run_filter_val_iter(&filter, Val::Null, &FilterVars::new()).collect();
+        assert_eq!(
+            val_to_json(results[0].as_ref().unwrap()).unwrap(),
+            json!([])
+        );
+        assert!(run_filter(&compile_filter("input").unwrap(), json!(null)).is_err());
+    }
+
+    #[test]
+    fn test_filter_error_locates_value_with_input_filename() {
+        use crate::{InputDoc, InputStream};
+
+        let docs = [Ok(InputDoc {
+            value: Val::from(1isize),
+            filename: Some("a.json".to_string()),
+            format: crate::Format::Json,
+        })];
+        let stream = InputStream::new(docs.into_iter());
+        let first = stream.next_doc().unwrap().unwrap();
+        let filter = compile_filter("input_filename | .x").unwrap();
+        let error = run_filter_with_inputs(&filter, first.value, &FilterVars::new(), &stream)
+            .find_map(Result::err);
+        let error = match error {
+            Some(OqError::Filter(error)) => error,
+            other => panic!("expected a filter error, got {:?}", other),
+        };
+        // The replay sees the same file name as the run
+        assert_eq!(error.value.as_deref(), Some(r#""a.json""#));
+        assert_eq!(error.expression.as_deref(), Some(".x"));
+    }
 }
@@ -0,0 +1,309 @@
+//! Splitting large inputs into shards of whole documents
+//!
+//! A shard is a run of complete documents, about 1 MiB long,
+//! that can be parsed and filtered on its own, e.g. on another thread. JSON
+//! is split at line ends between top-level values, YAML before `---`
+//! document markers and raw text between lines. Inputs in other formats are
+//! a single document, so they are a single shard.
+//!
+//! The input is read as it is split, so a stream of JSON or YAML documents
+//! never has to fit in memory. A single document, such as one large JSON
+//! value or an input in a format that is not split, is read whole.
+
+use crate::convert::{parse_raw_lines, parse_stream_val_with, Format, InputOptions, ValDocuments};
+use crate::OqError;
+use std::io::{self, Read};
+
+/// Size a shard grows to before it is cut at the next document boundary
+const SHARD_SIZE: usize = 1 << 20;
+
+/// Bytes read at a time
+const BLOCK_SIZE: usize = 64 << 10;
+
+/// A run of whole documents from a larger input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shard {
+    pub text: String,
+    /// Number of lines of the input before the shard
+    pub line: usize,
+    /// Byte offset of the shard in the input
+    pub offset: usize,
+}
+
+impl Shard {
+    /// Parse every document of the shard with input options
+    ///
+    /// Parse errors are located in the whole input, not in the shard.
+    pub fn documents_with(&self, format: Format, options: &InputOptions) -> ValDocuments<'_> {
+        let (line, offset) = (self.line, self.offset);
+        Box::new(
+            parse_stream_val_with(&self.text, format, options).map(move |doc| {
+                doc.map_err(|e| match e {
+                    OqError::Parse(diagnostic) => diagnostic.shifted(line, offset).into(),
+                    e => e,
+                })
+            }),
+        )
+    }
+
+    /// The lines of the shard as strings (like jq `-R`)
+    pub fn lines(&self) -> ValDocuments<'_> {
+        parse_raw_lines(&self.text)
+    }
+}
+
+/// Where documents of a format can be split
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Split {
+    /// After a line end outside of any string, array or object
+    Json,
+    /// Before a line that starts with `---`
+    Yaml,
+    /// After every line end
+    Lines,
+    /// Nowhere: the input is one document
+    Never,
+}
+
+/// Iterator over the shards of an input
+pub struct Shards<R> {
+    reader: R,
+    split: Split,
+    /// Shard size to reach before cutting
+    size: usize,
+    /// Input read but not yet returned in a shard
+    buf: Vec<u8>,
+    /// How much of `buf` has been scanned for a boundary
+    scanned: usize,
+    /// Where the next shard ends, once found
+    boundary: Option<usize>,
+    /// JSON scanner state: nesting depth, and whether it is in a string
+    /// and after a backslash in it
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    line: usize,
+    offset: usize,
+    done: bool,
+}
+
+impl<R: Read> Shards<R> {
+    /// Split the documents of an input in `format`
+    pub fn new(reader: R, format: Format) -> Self {
+        let split = match format {
+            Format::Json => Split::Json,
+            Format::Yaml => Split::Yaml,
+            _ => Split::Never,
+        };
+        Self::with_split(reader, split)
+    }
+
+    /// Split text between lines, for raw input
+    pub fn lines(reader: R) -> Self {
+        Self::with_split(reader, Split::Lines)
+    }
+
+    fn with_split(reader: R, split: Split) -> Self {
+        Self {
+            reader,
+            split,
+            size: SHARD_SIZE,
+            buf: Vec::new(),
+            scanned: 0,
+            boundary: None,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            line: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Cut shards once they reach `size` bytes instead of [`SHARD_SIZE`]
+    pub fn with_size(mut self, size: usize) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// Scan the rest of the buffer for the first boundary at least `size`
+    /// bytes in, and stop there
+    fn scan(&mut self) {
+        let from = self.scanned;
+        let found = |i: usize| (i >= self.size).then_some(i);
+        let boundary = match self.split {
+            Split::Json => {
+                let mut boundary = None;
+                for (i, &b) in self.buf.iter().enumerate().skip(from) {
+                    if self.in_string {
+                        match b {
+                            _ if self.escaped => self.escaped = false,
+                            b'\\' => self.escaped = true,
+                            b'"' => self.in_string = false,
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    match b {
+                        b'"' => self.in_string = true,
+                        b'{' | b'[' => self.depth += 1,
+                        b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                        b'\n' if self.depth == 0 => boundary = found(i + 1),
+                        _ => {}
+                    }
+                    if boundary.is_some() {
+                        break;
+                    }
+                }
+                boundary
+            }
+            Split::Lines => self.buf[from..]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'\n')
+                .find_map(|(i, _)| found(from + i + 1)),
+            Split::Yaml => {
+                // Only whole lines are scanned, so `scanned` stays at the
+                // start of a line
+                let mut start = from;
+                let mut boundary = None;
+                while let Some(end) = self.buf[start..].iter().position(|&b| b == b'\n') {
+                    let line = &self.buf[start..start + end];
+                    if start > 0 && is_document_marker(line) {
+                        boundary = found(start);
+                    }
+                    if boundary.is_some() {
+                        break;
+                    }
+                    start += end + 1;
+                }
+                self.scanned = start;
+                self.boundary = boundary;
+                return;
+            }
+            Split::Never => None,
+        };
+        self.boundary = boundary;
+        self.scanned = boundary.unwrap_or(self.buf.len());
+    }
+
+    /// Take the first `len` bytes of the buffer as a shard
+    ///
+    /// `len` is a boundary, so scanning starts over outside of any value.
+    fn take(&mut self, len: usize) -> io::Result<Shard> {
+        let bytes: Vec<u8> = self.buf.drain(..len).collect();
+        self.scanned = 0;
+        self.boundary = None;
+        self.depth = 0;
+        self.in_string = false;
+        self.escaped = false;
+        let line = self.line;
+        let offset = self.offset;
+        self.line += bytes.iter().filter(|&&b| b == b'\n').count();
+        self.offset += len;
+        let text =
+            String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Shard { text, line, offset })
+    }
+}
+
+impl<R: Read> Iterator for Shards<R> {
+    type Item = io::Result<Shard>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.boundary.is_none() && self.scanned < self.buf.len() {
+                self.scan();
+            }
+            if let Some(boundary) = self.boundary {
+                return Some(self.take(boundary));
+            }
+            if self.done {
+                return match self.buf.len() {
+                    0 => None,
+                    len => Some(self.take(len)),
+                };
+            }
+
+            let start = self.buf.len();
+            self.buf.resize(start + BLOCK_SIZE, 0);
+            let read = self.reader.read(&mut self.buf[start..]);
+            self.buf.truncate(start + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => self.done = true,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// A YAML `---` line, which starts a document
+fn is_document_marker(line: &[u8]) -> bool {
+    line.strip_prefix(b"---")
+        .is_some_and(|rest| rest.first().is_none_or(|b| b" \t\r".contains(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shards(input: &str, format: Format, size: usize) -> Vec<Shard> {
+        Shards::new(input.as_bytes(), format)
+            .with_size(size)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn texts(shards: &[Shard]) -> Vec<&str> {
+        shards.iter().map(|shard| shard.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_json_splits_between_values() {
+        let input = "{\"a\": 1}\n{\"b\": [1,\n2]}\n\"x\\\"\n\"\n3";
+        let shards = shards(input, Format::Json, 1);
+        assert_eq!(
+            texts(&shards),
+            ["{\"a\": 1}\n", "{\"b\": [1,\n2]}\n", "\"x\\\"\n\"\n", "3"]
+        );
+        assert_eq!(shards[2].line, 3);
+        assert_eq!(shards[2].offset, 23);
+    }
+
+    #[test]
+    fn test_yaml_splits_before_markers() {
+        let input = "a: 1\n---\nb: |\n  ---x\n--- # c\nc: 3\n";
+        let shards = shards(input, Format::Yaml, 1);
+        assert_eq!(
+            texts(&shards),
+            ["a: 1\n", "---\nb: |\n  ---x\n", "--- # c\nc: 3\n"]
+        );
+    }
+
+    #[test]
+    fn test_toml_is_one_shard() {
+        let shards = shards("a = 1\n\nb = 2\n", Format::Toml, 1);
+        assert_eq!(texts(&shards), ["a = 1\n\nb = 2\n"]);
+    }
+
+    #[test]
+    fn test_errors_are_located_in_the_input() {
+        let shards = shards("1\n2\n{\"a\" 1}\n", Format::Json, 1);
+        let error = shards[2]
+            .documents_with(Format::Json, &InputOptions::default())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        let OqError::Parse(diagnostic) = error else {
+            panic!("expected a parse error");
+        };
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (3, 6));
+        assert_eq!(location.span.start, 9);
+    }
+}
@@ -0,0 +1,236 @@
+//! Direct serde support for jaq values
+//!
+//! Format parsers deserialize straight into [`Val`] and encoders serialize
+//! straight from it, so documents never pass through `serde_json::Value` on
+//! their way into or out of a filter.
+
+use crate::diagnostic::FilterError;
+use crate::OqError;
+use jaq_json::{Num, Rc, Val};
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+
+/// A [`Val`] that can be deserialized from any self-describing format
+pub(crate) struct DeVal(pub Val);
+
+impl<'de> Deserialize<'de> for DeVal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValVisitor).map(DeVal)
+    }
+}
+
+struct ValVisitor;
+
+impl<'de> Visitor<'de> for ValVisitor {
+    type Value = Val;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Val, E> {
+        Ok(Val::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Val, E> {
+        Ok(Val::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Val, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Val, E> {
+        Ok(Val::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Val, E> {
+        Ok(isize::try_from(i)
+            .map(Val::from)
+            .unwrap_or_else(|_| decimal(i.to_string())))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Val, E> {
+        Ok(usize::try_from(u)
+            .map(Val::from)
+            .unwrap_or_else(|_| decimal(u.to_string())))
+    }
+
+    fn visit_i128<E>(self, i: i128) -> Result<Val, E> {
+        Ok(decimal(i.to_string()))
+    }
+
+    fn visit_u128<E>(self, u: u128) -> Result<Val, E> {
+        Ok(decimal(u.to_string()))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Val, E> {
+        Ok(Val::from(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Val, E> {
+        Ok(Val::from(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Val, E> {
+        Ok(Val::from(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Val, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(DeVal(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Val::Arr(Rc::new(items)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Val, A::Error> {
+        let mut obj = jaq_json::Map::default();
+        while let Some((DeVal(key), DeVal(value))) = map.next_entry()? {
+            // Non-string keys (e.g. YAML `1: a`) become their JSON text
+            let key = match key {
+                key @ Val::Str(..) => key,
+                other => Val::from(other.to_string()),
+            };
+            obj.insert(key, value);
+        }
+        Ok(Val::obj(obj))
+    }
+}
+
+/// A number kept as its decimal text so that no precision is lost
+fn decimal(n: String) -> Val {
+    Val::Num(Num::Dec(Rc::new(n)))
+}
+
+/// A [`Val`] reference that can be serialized into any format
+pub(crate) struct SerVal<'a>(pub &'a Val);
+
+impl Serialize for SerVal<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Val::Null => serializer.serialize_unit(),
+            Val::Bool(b) => serializer.serialize_bool(*b),
+            Val::Num(n) => serialize_num(n, serializer),
+            Val::Str(s, _) => serializer.serialize_str(&String::from_utf8_lossy(s)),
+            Val::Arr(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items.iter() {
+                    seq.serialize_element(&SerVal(item))?;
+                }
+                seq.end()
+            }
+            Val::Obj(obj) => {
+                let mut map = serializer.serialize_map(Some(obj.len()))?;
+                for (key, value) in obj.iter() {
+                    map.serialize_entry(&key_string(key), &SerVal(value))?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+fn serialize_num<S: Serializer>(n: &Num, serializer: S) -> Result<S::Ok, S::Error> {
+    match n {
+        Num::Int(i) => serializer.serialize_i64(*i as i64),
+        Num::Float(f) => serializer.serialize_f64(*f),
+        // Big integers and decimals: use the narrowest exact type available
+        n => {
+            let text = n.to_string();
+            if let Ok(i) = text.parse::<i64>() {
+                serializer.serialize_i64(i)
+            } else if let Ok(u) = text.parse::<u64>() {
+                serializer.serialize_u64(u)
+            } else {
+                let f = text.parse::<f64>().map_err(serde::ser::Error::custom)?;
+                serializer.serialize_f64(f)
+            }
+        }
+    }
+}
+
+/// A copy of a value with the keys of every object sorted
+pub(crate) fn sort_keys(value: &Val) -> Val {
+    match value {
+        Val::Arr(items) => Val::Arr(Rc::new(items.iter().map(sort_keys).collect())),
+        Val::Obj(obj) => {
+            let mut entries: Vec<_> = obj.iter().map(|(k, v)| (k.clone(), sort_keys(v))).collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Val::obj(entries.into_iter().collect())
+        }
+        other => other.clone(),
+    }
+}
+
+/// The string form of an object key
+pub fn key_string(key: &Val) -> String {
+    match key {
+        Val::Str(s, _) => String::from_utf8_lossy(s).into_owned(),
+        other => other.to_string(),
+    }
+}
+
+/// Convert a jaq value into a `serde_json::Value`
+pub fn val_to_json(value: &Val) -> Result<serde_json::Value, OqError> {
+    value
+        .try_into()
+        .map_err(|_| FilterError::new("Failed to convert output to JSON").into())
+}
+
+/// Convert a `serde_json::Value` into a jaq value
+pub fn json_to_val(value: serde_json::Value) -> Val {
+    value.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse_json(input: &str) -> Val {
+        serde_json::from_str::<DeVal>(input).unwrap().0
+    }
+
+    #[test]
+    fn test_deserialize_matches_json_conversion() {
+        let input =
+            r#"{"name": "Ada", "tags": ["a", "b"], "n": -3, "f": 1.5, "ok": true, "x": null}"#;
+        let direct = parse_json(input);
+        let via_json = json_to_val(serde_json::from_str(input).unwrap());
+        assert_eq!(direct, via_json);
+    }
+
+    #[test]
+    fn test_large_integers_keep_precision() {
+        let value = parse_json("18446744073709551615");
+        assert_eq!(value.to_string(), "18446744073709551615");
+        assert_eq!(
+            serde_json::to_string(&SerVal(&value)).unwrap(),
+            "18446744073709551615"
+        );
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let expected = json!({"b": [1, 2.5, "x"], "a": {"nested": null}});
+        let value = json_to_val(expected.clone());
+        let text = serde_json::to_string(&SerVal(&value)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_yaml_non_string_keys() {
+        let value = serde_yaml::from_str::<DeVal>("1: one\ntrue: yes")
+            .unwrap()
+            .0;
+        assert_eq!(
+            val_to_json(&value).unwrap(),
+            json!({"1": "one", "true": "yes"})
+        );
+    }
+}
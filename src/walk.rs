@@ -0,0 +1,201 @@
+//! Expanding input arguments into files
+//!
+//! An argument that names a directory is walked recursively, and one that
+//! is a glob such as `charts/**/Chart.yaml` is matched against the files
+//! under its leading directory. Walks skip hidden files and files ignored by
+//! `.gitignore` or `.ignore`, like ripgrep.
+
+use crate::convert::Format;
+use crate::OqError;
+use globset::GlobBuilder;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Finds the input files named by a file, directory or glob argument
+///
+/// Files found by a walk come out sorted by path, so the order does not
+/// depend on the file system.
+#[derive(Debug, Clone, Default)]
+pub struct FileFinder {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    no_ignore: bool,
+}
+
+impl FileFinder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep walked files that match `glob` (gitignore syntax, e.g.
+    /// `*.yaml` or `charts/**`)
+    ///
+    /// Without include globs, a directory walk keeps the files with a known
+    /// extension.
+    pub fn with_include(mut self, glob: impl Into<String>) -> Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    /// Skip walked files that match `glob` (gitignore syntax)
+    pub fn with_exclude(mut self, glob: impl Into<String>) -> Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Also walk hidden files and files ignored by `.gitignore` or `.ignore`
+    pub fn with_no_ignore(mut self, no_ignore: bool) -> Self {
+        self.no_ignore = no_ignore;
+        self
+    }
+
+    /// The files named by `arg`
+    ///
+    /// A file, or a path that does not exist and is not a glob, is returned
+    /// as-is so that reading it reports the error. So is a glob that matches
+    /// nothing. The walk stops at the first entry that cannot be read.
+    pub fn find(&self, arg: &str) -> Result<Vec<PathBuf>, OqError> {
+        self.find_with(arg, Err)
+    }
+
+    /// The files named by `arg`, handing entries that cannot be read to
+    /// `on_error`
+    ///
+    /// The walk goes on if `on_error` returns `Ok`, and stops with its error
+    /// otherwise.
+    pub fn find_with(
+        &self,
+        arg: &str,
+        on_error: impl FnMut(OqError) -> Result<(), OqError>,
+    ) -> Result<Vec<PathBuf>, OqError> {
+        let path = Path::new(arg);
+        if path.is_dir() {
+            // Include globs replace the extension check
+            let any_file = !self.include.is_empty();
+            return self.walk(path, None, on_error, |path| {
+                any_file || Format::from_path(path).is_some()
+            });
+        }
+        if path.exists() || !is_glob(arg) {
+            return Ok(vec![path.to_path_buf()]);
+        }
+
+        let (root, depth) = glob_root(arg);
+        if !root.is_dir() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+        // Walked paths have no leading `./`, so the glob must not have one
+        let pattern = arg.trim_start_matches("./");
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| OqError::Usage(format!("invalid glob `{}`: {}", arg, e)))?
+            .compile_matcher();
+        let found = self.walk(&root, depth, on_error, |path| glob.is_match(path))?;
+        if found.is_empty() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+        Ok(found)
+    }
+
+    /// Files under `root` that pass the include, exclude and ignore filters
+    /// and `keep`
+    fn walk(
+        &self,
+        root: &Path,
+        max_depth: Option<usize>,
+        mut on_error: impl FnMut(OqError) -> Result<(), OqError>,
+        keep: impl Fn(&Path) -> bool,
+    ) -> Result<Vec<PathBuf>, OqError> {
+        let mut overrides = OverrideBuilder::new(root);
+        let globs = self.include.iter().cloned();
+        let excluded = self.exclude.iter().map(|glob| format!("!{}", glob));
+        for glob in globs.chain(excluded) {
+            overrides
+                .add(&glob)
+                .map_err(|e| OqError::Usage(format!("invalid glob `{}`: {}", glob, e)))?;
+        }
+        let overrides = overrides
+            .build()
+            .map_err(|e| OqError::Usage(e.to_string()))?;
+
+        let walk = WalkBuilder::new(root)
+            .standard_filters(!self.no_ignore)
+            .max_depth(max_depth)
+            .overrides(overrides)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+
+        let mut files = Vec::new();
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    on_error(OqError::Io(io::Error::other(e)))?;
+                    continue;
+                }
+            };
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            // Globs are matched against the path as written, without `./`
+            let path = entry.path();
+            let path = path.strip_prefix(".").unwrap_or(path);
+            if keep(path) {
+                files.push(path.to_path_buf());
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Whether `arg` has glob syntax
+fn is_glob(arg: &str) -> bool {
+    arg.contains(['*', '?', '[', '{'])
+}
+
+/// The directory to walk for a glob, and how deep (`None` with `**`)
+fn glob_root(glob: &str) -> (PathBuf, Option<usize>) {
+    let components: Vec<&str> = glob.split('/').collect();
+    let literal = components.iter().take_while(|c| !is_glob(c)).count();
+    let root = match components[..literal].join("/") {
+        root if root.is_empty() && glob.starts_with('/') => "/".to_string(),
+        root if root.is_empty() => ".".to_string(),
+        root => root,
+    };
+    let depth = (!glob.contains("**")).then_some(components.len() - literal);
+    (PathBuf::from(root), depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_root() {
+        assert_eq!(
+            glob_root("charts/**/Chart.yaml"),
+            (PathBuf::from("charts"), None)
+        );
+        assert_eq!(glob_root("*.yaml"), (PathBuf::from("."), Some(1)));
+        assert_eq!(
+            glob_root("a/b/*/values.yaml"),
+            (PathBuf::from("a/b"), Some(2))
+        );
+    }
+
+    #[test]
+    fn test_plain_paths_are_kept() {
+        let finder = FileFinder::new();
+        assert_eq!(
+            finder.find("missing.json").unwrap(),
+            vec![PathBuf::from("missing.json")]
+        );
+        assert_eq!(
+            finder.find("missing/*.json").unwrap(),
+            vec![PathBuf::from("missing/*.json")]
+        );
+    }
+}
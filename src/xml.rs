@@ -0,0 +1,397 @@
+//! XML documents as values
+//!
+//! An XML document maps to an object with one key, the name of its root
+//! element. Each element becomes:
+//!
+//! - `null` when it is empty (`<a/>`) and a string when it only has text
+//!   (`<a>text</a>`)
+//! - otherwise an object with its attributes as `@name` keys, its text as a
+//!   `#text` key and its child elements under their names; children with the
+//!   same name are collected into an array, in document order
+//!
+//! Names are kept as written, with their prefixes (`soap:Body`), and
+//! namespace declarations are attributes like any other (`@xmlns:soap`).
+//! Text is trimmed, and the text around child elements is joined with
+//! spaces. Attribute values and text are always strings. Comments,
+//! processing instructions and the doctype are dropped.
+//!
+//! Encoding reverses the mapping: an object with a single element key is
+//! the root element, and any other value is wrapped in a `<root>` element.
+//! Arrays under a key are repeated elements, and arrays anywhere else have
+//! an `<item>` element per value.
+
+use crate::convert::Format;
+use crate::diagnostic::{Diagnostic, Location};
+use crate::val::key_string;
+use crate::OqError;
+use jaq_json::Val;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+/// Name of the element that wraps values without a single root element
+const ROOT: &str = "root";
+/// Name of the elements of an array that is not under a key
+const ITEM: &str = "item";
+
+/// An element being read, until its end tag
+struct Element {
+    name: String,
+    /// Byte offset of the start tag
+    offset: u64,
+    attributes: Vec<(String, String)>,
+    children: Vec<(String, Val)>,
+    /// Runs of text, split by child elements
+    text: Vec<String>,
+}
+
+impl Element {
+    fn new(start: &BytesStart, source: &str, offset: u64) -> Result<Self, OqError> {
+        let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+        let mut attributes = Vec::new();
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| error_at(source, offset, e))?;
+            let value = attribute
+                .unescape_value()
+                .map_err(|e| error_at(source, offset, e))?;
+            let key = String::from_utf8_lossy(attribute.key.as_ref());
+            attributes.push((format!("@{}", key), value.into_owned()));
+        }
+        Ok(Self {
+            name,
+            offset,
+            attributes,
+            children: Vec::new(),
+            text: vec![String::new()],
+        })
+    }
+
+    fn push_child(&mut self, name: String, value: Val) {
+        self.children.push((name, value));
+        self.text.push(String::new());
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if let Some(run) = self.text.last_mut() {
+            run.push_str(text);
+        }
+    }
+
+    fn into_val(self) -> Val {
+        let text: Vec<&str> = self
+            .text
+            .iter()
+            .map(|run| run.trim())
+            .filter(|run| !run.is_empty())
+            .collect();
+        let text = text.join(" ");
+        if self.attributes.is_empty() && self.children.is_empty() {
+            return if text.is_empty() {
+                Val::Null
+            } else {
+                Val::from(text)
+            };
+        }
+
+        let mut entries: Vec<(String, Vec<Val>)> = Vec::new();
+        for (key, value) in self.attributes {
+            entries.push((key, vec![Val::from(value)]));
+        }
+        if !text.is_empty() {
+            entries.push(("#text".to_string(), vec![Val::from(text)]));
+        }
+        for (name, value) in self.children {
+            match entries.iter_mut().find(|(key, _)| *key == name) {
+                Some((_, values)) => values.push(value),
+                None => entries.push((name, vec![value])),
+            }
+        }
+        let entries = entries.into_iter().map(|(key, mut values)| {
+            let value = match values.len() {
+                1 => values.remove(0),
+                _ => Val::from_iter(values),
+            };
+            (Val::from(key), value)
+        });
+        Val::obj(entries.collect())
+    }
+}
+
+/// Parse an XML document into an object keyed by its root element
+pub(crate) fn parse(input: &str) -> Result<Val, OqError> {
+    let mut reader = Reader::from_str(input);
+    let mut open: Vec<Element> = Vec::new();
+    let mut root: Option<Val> = None;
+
+    loop {
+        // Errors found after an event was read are located at its start
+        let offset = reader.buffer_position();
+        let event = reader
+            .read_event()
+            .map_err(|e| error_at(input, reader.error_position(), e))?;
+        let text = match event {
+            Event::Start(start) => {
+                open.push(Element::new(&start, input, offset)?);
+                continue;
+            }
+            Event::Empty(start) => {
+                let element = Element::new(&start, input, offset)?;
+                close(element, &mut open, &mut root, input)?;
+                continue;
+            }
+            Event::End(_) => {
+                // The reader checks that end tags match their start tags
+                if let Some(element) = open.pop() {
+                    close(element, &mut open, &mut root, input)?;
+                }
+                continue;
+            }
+            Event::Text(text) => text.decode().map_err(|e| error_at(input, offset, e))?,
+            Event::CData(data) => data.decode().map_err(|e| error_at(input, offset, e))?,
+            Event::GeneralRef(reference) => {
+                let resolved = match reference.resolve_char_ref() {
+                    Ok(Some(c)) => c.to_string(),
+                    Ok(None) => {
+                        let name = reference.decode().map_err(|e| error_at(input, offset, e))?;
+                        match resolve_predefined_entity(&name) {
+                            Some(text) => text.to_string(),
+                            None => {
+                                let message = format!("unknown entity `&{};`", name);
+                                return Err(error_at(input, offset, message));
+                            }
+                        }
+                    }
+                    Err(e) => return Err(error_at(input, offset, e)),
+                };
+                resolved.into()
+            }
+            Event::Eof => break,
+            Event::Comment(_) | Event::Decl(_) | Event::PI(_) | Event::DocType(_) => continue,
+        };
+        match open.last_mut() {
+            Some(element) => element.push_text(&text),
+            None if text.trim().is_empty() => {}
+            None => {
+                let start = offset + (text.len() - text.trim_start().len()) as u64;
+                return Err(error_at(input, start, "text outside of the root element"));
+            }
+        }
+    }
+
+    if let Some(element) = open.last() {
+        let message = format!("element `{}` is not closed", element.name);
+        return Err(error_at(input, element.offset, message));
+    }
+    root.ok_or_else(|| error_at(input, reader.buffer_position(), "no root element"))
+}
+
+/// Add a finished element to its parent, or make it the root
+fn close(
+    element: Element,
+    open: &mut [Element],
+    root: &mut Option<Val>,
+    source: &str,
+) -> Result<(), OqError> {
+    let (name, offset) = (element.name.clone(), element.offset);
+    let value = element.into_val();
+    match open.last_mut() {
+        Some(parent) => parent.push_child(name, value),
+        None if root.is_some() => {
+            let message = format!("more than one root element (`{}`)", name);
+            return Err(error_at(source, offset, message));
+        }
+        None => *root = Some(Val::obj([(Val::from(name), value)].into_iter().collect())),
+    }
+    Ok(())
+}
+
+/// A parse error at a byte offset of the source
+fn error_at(source: &str, offset: u64, message: impl ToString) -> OqError {
+    let position = (offset as usize).min(source.len());
+    Diagnostic::new(Format::Xml, message.to_string())
+        .with_location(Location::from_span(source, position..position + 1))
+        .into()
+}
+
+/// Encode a value as an XML document, indented by `indent` characters
+/// (`None` for a single line)
+pub(crate) fn encode(value: &Val, indent: Option<(u8, usize)>) -> Result<String, OqError> {
+    let mut writer = match indent {
+        Some((c, size)) => Writer::new_with_indent(Vec::new(), c, size),
+        None => Writer::new(Vec::new()),
+    };
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+
+    let root = match value {
+        Val::Obj(obj) if obj.len() == 1 => obj
+            .iter()
+            .next()
+            .map(|(key, value)| (key_string(key), value))
+            .filter(|(key, value)| is_element_key(key) && !matches!(value, Val::Arr(_))),
+        _ => None,
+    };
+    match root {
+        Some((name, value)) => write_element(&mut writer, &name, value)?,
+        None => write_element(&mut writer, ROOT, value)?,
+    }
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+/// Whether an object key is a child element rather than an attribute or text
+fn is_element_key(key: &str) -> bool {
+    !key.starts_with('@') && key != "#text"
+}
+
+/// Write the value under an object key: arrays become repeated elements
+fn write_field(writer: &mut Writer<Vec<u8>>, name: &str, value: &Val) -> Result<(), OqError> {
+    match value {
+        Val::Arr(values) => values
+            .iter()
+            .try_for_each(|value| write_element(writer, name, value)),
+        value => write_element(writer, name, value),
+    }
+}
+
+fn write_element(writer: &mut Writer<Vec<u8>>, name: &str, value: &Val) -> Result<(), OqError> {
+    if !is_name(name) {
+        let message = format!("cannot encode `{}` as an element name", name);
+        return Err(OqError::Encode(Format::Xml, message));
+    }
+    let mut start = BytesStart::new(name);
+    match value {
+        Val::Null => writer.write_event(Event::Empty(start))?,
+        Val::Arr(values) if values.is_empty() => writer.write_event(Event::Empty(start))?,
+        Val::Arr(values) => {
+            writer.write_event(Event::Start(start))?;
+            for value in values.iter() {
+                write_element(writer, ITEM, value)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new(name)))?;
+        }
+        Val::Obj(obj) => {
+            let mut text = None;
+            let mut children = Vec::new();
+            for (key, value) in obj.iter() {
+                let key = key_string(key);
+                if let Some(attribute) = key.strip_prefix('@') {
+                    if !is_name(attribute) {
+                        let message = format!("cannot encode `{}` as an attribute name", key);
+                        return Err(OqError::Encode(Format::Xml, message));
+                    }
+                    start.push_attribute((attribute, text_of(value).as_str()));
+                } else if key == "#text" {
+                    text = Some(text_of(value));
+                } else {
+                    children.push((key, value));
+                }
+            }
+            if text.is_none() && children.is_empty() {
+                writer.write_event(Event::Empty(start))?;
+                return Ok(());
+            }
+            writer.write_event(Event::Start(start))?;
+            if let Some(text) = text {
+                writer.write_event(Event::Text(BytesText::new(&text)))?;
+            }
+            for (key, value) in children {
+                write_field(writer, &key, value)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new(name)))?;
+        }
+        value => {
+            writer.write_event(Event::Start(start))?;
+            writer.write_event(Event::Text(BytesText::new(&text_of(value))))?;
+            writer.write_event(Event::End(BytesEnd::new(name)))?;
+        }
+    }
+    Ok(())
+}
+
+/// The text of an attribute or text node: strings as-is, null as nothing
+/// and anything else as JSON
+fn text_of(value: &Val) -> String {
+    match value {
+        Val::Null => String::new(),
+        value => key_string(value),
+    }
+}
+
+/// Whether `name` is a valid XML name (with an optional prefix)
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::val::{json_to_val, val_to_json};
+    use serde_json::json;
+
+    fn parse_json(input: &str) -> serde_json::Value {
+        val_to_json(&parse(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let input = r#"<?xml version="1.0"?>
+<!-- a project -->
+<project xmlns="http://maven.apache.org/POM/4.0.0" version="4">
+  <name>oq &amp; co</name>
+  <dep id="a"/>
+  <dep id="b">core</dep>
+  <empty/>
+  <note>Hello <b>big</b> world &#x21;</note>
+  <code><![CDATA[a < b]]></code>
+</project>"#;
+        assert_eq!(
+            parse_json(input),
+            json!({"project": {
+                "@xmlns": "http://maven.apache.org/POM/4.0.0",
+                "@version": "4",
+                "name": "oq & co",
+                "dep": [{"@id": "a"}, {"@id": "b", "#text": "core"}],
+                "empty": null,
+                "note": {"#text": "Hello world !", "b": "big"},
+                "code": "a < b",
+            }})
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for input in ["<a><b></a>", "<a>", "<a/><b/>", "text", ""] {
+            let error = parse(input).unwrap_err();
+            assert!(matches!(error, OqError::Parse(_)), "{}: {}", input, error);
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let input = r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg:svg xmlns:svg="http://www.w3.org/2000/svg" width="10">
+  <svg:title>a &lt; b</svg:title>
+  <svg:g id="1"/>
+  <svg:g id="2">x</svg:g>
+  <svg:desc/>
+</svg:svg>"#;
+        let value = parse(input).unwrap();
+        assert_eq!(encode(&value, Some((b' ', 2))).unwrap(), input);
+        assert_eq!(parse(&encode(&value, None).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encode_without_root() {
+        let value = json_to_val(json!([{"a": 1}, [true]]));
+        assert_eq!(
+            encode(&value, None).unwrap(),
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "<root><item><a>1</a></item><item><item>true</item></item></root>"
+            )
+        );
+        let error = encode(&json_to_val(json!({"a b": 1})), None).unwrap_err();
+        assert!(error.to_string().contains("`a b`"));
+    }
+}
@@ -0,0 +1,903 @@
+//! End-to-end tests for the `oq` binary
+
+use assert_cmd::{cargo::cargo_bin_cmd, Command};
+use predicates::prelude::*;
+
+fn oq() -> Command {
+    cargo_bin_cmd!("oq")
+}
+
+#[test]
+fn test_arg_and_argjson() {
+    oq().args(["-n", "-c", "--arg", "a", "x", "--argjson", "b", "{\"c\":1}"])
+        .arg("[$a, $b]")
+        .assert()
+        .success()
+        .stdout("[\"x\",{\"c\":1}]\n");
+}
+
+#[test]
+fn test_positional_args() {
+    oq().args(["-n", "-c", "$ARGS", "--args", "a", "b"])
+        .assert()
+        .success()
+        .stdout("{\"positional\":[\"a\",\"b\"],\"named\":{}}\n");
+
+    oq().args([
+        "-n",
+        "-c",
+        "$ARGS.positional",
+        "--jsonargs",
+        "1",
+        "{\"x\":2}",
+    ])
+    .assert()
+    .success()
+    .stdout("[1,{\"x\":2}]\n");
+
+    // Arguments before the flag are still input files
+    oq().args(["-c", "[.name, $ARGS.positional]", "tests/fixtures/app.json"])
+        .args(["--args", "a", "b"])
+        .assert()
+        .success()
+        .stdout("[\"app\",[\"a\",\"b\"]]\n");
+}
+
+#[test]
+fn test_slurpfile_reads_like_an_input() {
+    let people = "tests/fixtures/people.csv";
+    oq().args([
+        "-n",
+        "-c",
+        "--infer-types",
+        "$p[0][0]",
+        "--slurpfile",
+        "p",
+        people,
+    ])
+    .assert()
+    .success()
+    .stdout("{\"name\":\"Ada\",\"age\":36,\"admin\":true}\n");
+
+    oq().args(["-n", "-c", "-R", "$p[0]", "--slurpfile", "p", people])
+        .assert()
+        .success()
+        .stdout("\"name,age,admin\"\n");
+}
+
+#[test]
+fn test_undefined_variable() {
+    oq().args(["-n", "$nope"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("compile error"));
+}
+
+#[test]
+fn test_compile_error_snippet() {
+    oq().args(["-n", ".items | lenght"])
+        .assert()
+        .code(3)
+        .stderr(
+            "oq: compile error: undefined filter `lenght/0`\n --> <filter>:1:10\n  |\n1 | .items | lenght\n  |          ^^^^^^\n  = help: did you mean `length`?\n",
+        );
+}
+
+#[test]
+fn test_slurp_mixed_formats_outputs_json() {
+    oq().args(["-s", "-c", "map(.name)"])
+        .args(["tests/fixtures/app.json", "tests/fixtures/db.yaml"])
+        .assert()
+        .success()
+        .stdout("[\"app\",\"db\"]\n");
+}
+
+#[test]
+fn test_slurp_same_format_keeps_format() {
+    oq().args(["-s", "map(.port)"])
+        .args(["tests/fixtures/db.yaml", "tests/fixtures/cache.yaml"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("- 5432\n- 6379\n"));
+}
+
+#[test]
+fn test_json_lines_run_filter_per_document() {
+    oq().args(["-c", ".id"])
+        .write_stdin("{\"id\": 1}\n{\"id\": 2}\n")
+        .assert()
+        .success()
+        .stdout("1\n2\n");
+}
+
+#[test]
+fn test_yaml_stream_slurp() {
+    oq().args(["-s", "-o", "json", "-c", "map(.kind)"])
+        .arg("tests/fixtures/manifests.yaml")
+        .assert()
+        .success()
+        .stdout("[\"Service\",\"Deployment\"]\n");
+}
+
+#[test]
+fn test_outputs_written_before_error() {
+    oq().args(["-n", "1, error(\"boom\"), 2"])
+        .assert()
+        .failure()
+        .stdout("1\n")
+        .stderr(predicate::str::contains("boom"));
+}
+
+#[test]
+fn test_color_always_highlights() {
+    oq().args(["--color", "always", "-o", "toon", "."])
+        .arg("tests/fixtures/app.json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\u{1b}[34;1mname\u{1b}[0m"))
+        .stdout(predicate::str::contains("\u{1b}[0;32mapp\u{1b}[0m"));
+}
+
+#[test]
+fn test_color_auto_off_when_piped() {
+    oq().args(["-c", "."])
+        .env("TERM", "xterm-256color")
+        .write_stdin("{\"a\": 1}")
+        .assert()
+        .success()
+        .stdout("{\"a\":1}\n");
+}
+
+#[test]
+fn test_oq_colors_overrides_theme() {
+    oq().args(["--color", "always", "-i", "json", "-c", "."])
+        .env("OQ_COLORS", "0;31:0;31:0;31:1;33")
+        .write_stdin("[1]")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\u{1b}[1;33m1\u{1b}[0m"));
+}
+
+#[test]
+fn test_invalid_oq_colors_warns_in_error_format() {
+    oq().args(["--color", "always", "--error-format", "json", "-n", "1"])
+        .env("OQ_COLORS", "nope")
+        .assert()
+        .success()
+        .stderr("{\"kind\":\"Warning\",\"message\":\"invalid OQ_COLORS: nope\"}\n");
+}
+
+#[test]
+fn test_sort_keys_applies_to_yaml() {
+    oq().args(["-S", "-o", "yaml", "."])
+        .write_stdin("{\"b\": 1, \"a\": {\"d\": 2, \"c\": 3}}")
+        .assert()
+        .success()
+        .stdout("a:\n  c: 3\n  d: 2\nb: 1\n");
+}
+
+#[test]
+fn test_tab_indent() {
+    oq().args(["--tab", "."])
+        .write_stdin("{\"a\": [1]}")
+        .assert()
+        .success()
+        .stdout("{\n\t\"a\": [\n\t\t1\n\t]\n}\n");
+}
+
+#[test]
+fn test_join_output() {
+    oq().args(["-j", ".[]"])
+        .write_stdin("[\"a\", 1, \"b\"]")
+        .assert()
+        .success()
+        .stdout("a1b");
+}
+
+#[test]
+fn test_raw_output0() {
+    oq().args(["--raw-output0", ".[]"])
+        .write_stdin("[\"a\", \"b\"]")
+        .assert()
+        .success()
+        .stdout("a\0b\0");
+}
+
+#[test]
+fn test_ascii_output() {
+    oq().args(["-a", "."])
+        .write_stdin("\"café\"")
+        .assert()
+        .success()
+        .stdout("\"caf\\u00e9\"\n");
+}
+
+#[test]
+fn test_exit_status_false_and_empty() {
+    oq().args(["-e", ".a"])
+        .write_stdin("{\"a\": false}")
+        .assert()
+        .code(1);
+    oq().args(["-e", "empty"])
+        .write_stdin("{}")
+        .assert()
+        .code(4);
+    oq().args(["-e", ".a"])
+        .write_stdin("{\"a\": 0}")
+        .assert()
+        .code(0);
+}
+
+#[test]
+fn test_exit_codes_per_error_class() {
+    oq().args(["--argjson", "x", "{", "-n", "$x"])
+        .assert()
+        .code(2);
+    oq().args(["-n", "nosuchfn"]).assert().code(3);
+    oq().args(["-n", "error(\"x\")"]).assert().code(5);
+    oq().args(["-i", "json", "."])
+        .write_stdin("{")
+        .assert()
+        .code(6);
+    oq().args([".", "tests/fixtures/missing.json"])
+        .assert()
+        .code(7);
+}
+
+#[test]
+fn test_filter_from_file_with_library_path() {
+    oq().args([
+        "-s",
+        "-L",
+        "tests/fixtures/jq",
+        "-f",
+        "tests/fixtures/jq/ports.jq",
+    ])
+    .args(["tests/fixtures/app.json", "tests/fixtures/db.yaml"])
+    .assert()
+    .success()
+    .stdout("5432\n");
+}
+
+#[test]
+fn test_include_inline_filter() {
+    oq().args(["-c", "-L", "tests/fixtures/jq", "include \"util\"; names"])
+        .write_stdin("[{\"name\": \"a\"}]")
+        .assert()
+        .success()
+        .stdout("[\"a\"]\n");
+}
+
+#[test]
+fn test_missing_filter_file_is_usage_error() {
+    oq().args(["-n", "-f", "tests/fixtures/jq/missing.jq"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("tests/fixtures/jq/missing.jq"));
+}
+
+#[test]
+fn test_missing_module_is_compile_error() {
+    oq().args(["-n", "-L", "tests/fixtures/jq", "import \"nope\" as n; 1"])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn test_raw_input_lines() {
+    oq().args(["-R", "-c", "split(\" \") | {method: .[0], path: .[1]}"])
+        .write_stdin("GET /a\nPOST /b")
+        .assert()
+        .success()
+        .stdout("{\"method\":\"GET\",\"path\":\"/a\"}\n{\"method\":\"POST\",\"path\":\"/b\"}\n");
+}
+
+#[test]
+fn test_raw_input_slurp() {
+    oq().args(["-R", "-s", "-o", "yaml", "{text: .}"])
+        .write_stdin("not: [valid\n")
+        .assert()
+        .success()
+        .stdout("text: |\n  not: [valid\n");
+}
+
+#[test]
+fn test_inputs_across_files() {
+    oq().args(["-n", "-c", "[inputs | .name]"])
+        .args(["tests/fixtures/app.json", "tests/fixtures/db.yaml"])
+        .assert()
+        .success()
+        .stdout("[\"app\",\"db\"]\n");
+}
+
+#[test]
+fn test_cross_file_join() {
+    let filter = "input as $cfg | [inputs | {name, replicas: $cfg[.name].replicas}]";
+    oq().args(["-n", "-c", filter])
+        .args(["tests/fixtures/services.toml", "tests/fixtures/db.yaml"])
+        .arg("tests/fixtures/cache.yaml")
+        .assert()
+        .success()
+        .stdout("[{\"name\":\"db\",\"replicas\":2},{\"name\":\"cache\",\"replicas\":1}]\n");
+}
+
+#[test]
+fn test_input_filename() {
+    oq().args(["-r", "input_filename"])
+        .args(["tests/fixtures/app.json", "tests/fixtures/db.yaml"])
+        .assert()
+        .success()
+        .stdout("tests/fixtures/app.json\ntests/fixtures/db.yaml\n");
+}
+
+#[test]
+fn test_raw_input_with_inputs() {
+    oq().args(["-n", "-R", "-c", "[inputs | ascii_upcase]"])
+        .write_stdin("a\nb\n")
+        .assert()
+        .success()
+        .stdout("[\"A\",\"B\"]\n");
+}
+
+#[test]
+fn test_format_from_extension() {
+    // Dotted keys sniff as neither JSON nor YAML; the extension settles it
+    oq().args(["-c", ".server", "-o", "json", "tests/fixtures/dotted.toml"])
+        .assert()
+        .success()
+        .stdout("{\"port\":8080,\"host\":\"localhost\"}\n");
+}
+
+#[test]
+fn test_format_override_prefix() {
+    oq().args([
+        "-c",
+        "-o",
+        "json",
+        "-i",
+        "json",
+        ".",
+        "yaml:tests/fixtures/settings.conf",
+    ])
+    .assert()
+    .success()
+    .stdout("{\"name\":\"api\",\"replicas\":3}\n");
+    oq().args(["-r", "input_filename", "yaml:tests/fixtures/settings.conf"])
+        .assert()
+        .success()
+        .stdout("tests/fixtures/settings.conf\n");
+}
+
+#[test]
+fn test_key_value_stdin_is_yaml() {
+    oq().args(["-c", "-o", "json", "."])
+        .write_stdin("name: Ada\ntags:\n- a\n")
+        .assert()
+        .success()
+        .stdout("{\"name\":\"Ada\",\"tags\":[\"a\"]}\n");
+}
+
+#[test]
+fn test_explain_detect() {
+    oq().args(["--explain-detect", "-c", "-o", "json", "."])
+        .write_stdin("{a: 1}")
+        .assert()
+        .success()
+        .stdout("{\"a\":1}\n")
+        .stderr(predicate::str::starts_with(
+            "oq: <stdin>: json5 (json did not parse)\n  json   60",
+        ));
+    oq().args(["--explain-detect", ".", "tests/fixtures/dotted.toml"])
+        .assert()
+        .success()
+        .stderr("oq: tests/fixtures/dotted.toml: toml (from the file extension)\n");
+}
+
+#[test]
+fn test_parse_error_snippet() {
+    oq().args([".", "tests/fixtures/broken.json"])
+        .assert()
+        .code(6)
+        .stderr(
+            "oq: JSON parse error: expected `,` or `}`\n --> tests/fixtures/broken.json:3:3\n  |\n3 |   \"port\": 8080\n  |   ^\n",
+        );
+
+    // Broken TOML on stdin reports its error rather than being read as INI
+    oq().args(["-c", "."])
+        .write_stdin("a = [1, 2\n")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::starts_with(
+            "oq: TOML parse error: invalid array: expected `]`\n --> <stdin>:1:10\n",
+        ));
+}
+
+#[test]
+fn test_filter_error_context() {
+    oq().args([".services[] | .port + 1"])
+        .write_stdin(r#"{"services": [{"port": 80}, {"port": "http"}]}"#)
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "  input path: .services[1]\n  input value: {\"port\":\"http\"}\n  expression: .port + 1\n",
+        ));
+}
+
+#[test]
+fn test_error_format_json() {
+    let output = oq()
+        .args(["--error-format", "json", ".", "tests/fixtures/broken.json"])
+        .assert()
+        .code(6)
+        .get_output()
+        .stderr
+        .clone();
+    let error: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(error["kind"], "Parse");
+    assert_eq!(error["file"], "tests/fixtures/broken.json");
+    assert_eq!(
+        (error["line"].as_u64(), error["column"].as_u64()),
+        (Some(3), Some(3))
+    );
+
+    let output = oq()
+        .args(["--error-format", "json", "-n", ".a | lenght"])
+        .assert()
+        .code(3)
+        .get_output()
+        .stderr
+        .clone();
+    let error: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(error["kind"], "Compile");
+    assert_eq!(error["span"], serde_json::json!({"start": 5, "end": 11}));
+    assert_eq!(error["suggestion"], "length");
+
+    let output = oq()
+        .args(["--error-format", "json", "-n", "{a: 1} | .a | .[0]"])
+        .assert()
+        .code(5)
+        .get_output()
+        .stderr
+        .clone();
+    let error: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(error["expression"], ".[0]");
+    assert_eq!(
+        error["expression_location"]["span"],
+        serde_json::json!({"start": 14, "end": 18})
+    );
+
+    // Command line errors too
+    let output = oq()
+        .args(["--error-format=json", "--no-such-flag"])
+        .assert()
+        .code(2)
+        .get_output()
+        .stderr
+        .clone();
+    let error: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(error["kind"], "Usage");
+}
+
+#[test]
+fn test_error_format_sarif() {
+    let output = oq()
+        .args(["--error-format", "sarif", ".", "tests/fixtures/broken.json"])
+        .assert()
+        .code(6)
+        .get_output()
+        .stderr
+        .clone();
+    let log: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(log["version"], "2.1.0");
+    let result = &log["runs"][0]["results"][0];
+    assert_eq!(result["ruleId"], "Parse");
+    let location = &result["locations"][0]["physicalLocation"];
+    assert_eq!(
+        location["artifactLocation"]["uri"],
+        "tests/fixtures/broken.json"
+    );
+    assert_eq!(location["region"]["startLine"], 3);
+}
+
+#[test]
+fn test_keep_going() {
+    oq().args(["-c", ".name"])
+        .args(["tests/fixtures/broken.json", "missing.json"])
+        .arg("tests/fixtures/settings.conf")
+        .assert()
+        .code(6)
+        .stdout("")
+        .stderr(predicate::str::contains("missing.json").not());
+
+    oq().args(["--keep-going", "-c", ".name"])
+        .args(["tests/fixtures/broken.json", "missing.json"])
+        .arg("tests/fixtures/settings.conf")
+        .assert()
+        .code(6)
+        .stdout("api\n")
+        .stderr(predicate::str::contains("broken.json:3:3"))
+        .stderr(predicate::str::contains("missing.json: No such file"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_keep_going_walks_past_unreadable_directories() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("unreadable");
+    let locked = root.join("locked");
+    std::fs::create_dir_all(&locked).unwrap();
+    std::fs::write(root.join("a.json"), "{\"name\": \"a\"}").unwrap();
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions do not stop root
+    if std::fs::read_dir(&locked).is_ok() {
+        return;
+    }
+
+    oq().args(["-r", ".name"])
+        .arg(&root)
+        .assert()
+        .code(7)
+        .stdout("");
+    oq().args(["--keep-going", "-r", ".name"])
+        .arg(&root)
+        .assert()
+        .code(7)
+        .stdout("a\n")
+        .stderr(predicate::str::contains("locked"));
+}
+
+#[test]
+fn test_with_filename() {
+    oq().args(["-H", "-r", ".name", "tests/fixtures/settings.conf"])
+        .assert()
+        .success()
+        .stdout("tests/fixtures/settings.conf:api\n");
+
+    oq().args(["-H", "-c", ".a"])
+        .write_stdin("{\"a\": 1}")
+        .assert()
+        .success()
+        .stdout("{\"file\":null,\"value\":1}\n");
+}
+
+#[test]
+fn test_directory_walk() {
+    // Hidden files, files listed in .ignore and unknown extensions are skipped
+    oq().args(["-r", ".name", "tests/fixtures/tree"])
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n");
+
+    oq().args(["-r", ".name", "--no-ignore", "--exclude", "*.json"])
+        .arg("tests/fixtures/tree")
+        .assert()
+        .success()
+        .stdout("hidden\nb\nc\nskipped\n");
+
+    oq().args(["-r", ".name", "--include", "*.toml", "tests/fixtures/tree"])
+        .assert()
+        .success()
+        .stdout("c\n");
+}
+
+#[test]
+fn test_glob_input() {
+    oq().args(["-H", "-r", ".name", "tests/fixtures/tree/**/*.yaml"])
+        .assert()
+        .success()
+        .stdout("tests/fixtures/tree/nested/b.yaml:b\n");
+
+    oq().args(["-r", ".name", "./tests/fixtures/tree/**/*.yaml"])
+        .assert()
+        .success()
+        .stdout("b\n");
+
+    oq().args([".", "tests/fixtures/tree/*.xyz"])
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains("tree/*.xyz: No such file"));
+}
+
+#[test]
+fn test_parallel_output_keeps_file_order() {
+    let expected = "a\nb\nc\n".repeat(3);
+    let tree = "tests/fixtures/tree";
+    for threads in ["1", "4"] {
+        oq().args(["-r", ".name", "--threads", threads, tree, tree, tree])
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+}
+
+#[test]
+fn test_parallel_detects_large_toml() {
+    // Longer than the 64 KiB start that the format is detected from
+    let input: String = (0..3000)
+        .map(|i| format!("[table_{:04}]\nkey = \"value {}\"\n", i, i))
+        .collect();
+    assert!(input.len() > 64 << 10);
+    oq().args(["--parallel", "--threads", "4", "keys | length"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("3000\n");
+}
+
+#[test]
+fn test_parallel_shards_keep_input_order() {
+    // A few megabytes, so that the input is split into several shards
+    let input: String = (0..150_000).map(|i| format!("{{\"i\":{}}}\n", i)).collect();
+    let expected: String = (0..150_000).map(|i| format!("{}\n", i)).collect();
+
+    oq().args(["--parallel", "--threads", "4", "-c", ".i"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(expected);
+
+    let output = oq()
+        .args(["--parallel", "--unordered", "--threads", "4", "-c", ".i"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let mut ids: Vec<usize> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| line.parse().unwrap())
+        .collect();
+    ids.sort_unstable();
+    assert_eq!(ids, (0..150_000).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_csv_input() {
+    oq().args(["-o", "json", "-c", ".", "tests/fixtures/people.csv"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "[{\"name\":\"Ada\",\"age\":\"36\",\"admin\":\"true\"},",
+            "{\"name\":\"Lovelace, B\",\"age\":\"007\",\"admin\":\"\"}]\n"
+        ));
+
+    oq().args(["-c", "--infer-types", "-o", "json", ".[]"])
+        .arg("tests/fixtures/people.csv")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"name\":\"Ada\",\"age\":36,\"admin\":true}\n",
+            "{\"name\":\"Lovelace, B\",\"age\":\"007\",\"admin\":null}\n"
+        ));
+
+    oq().args(["-i", "tsv", "--no-header", "-c", "-o", "json", "."])
+        .write_stdin("a\tb\n1\t2\n")
+        .assert()
+        .success()
+        .stdout("[{\"0\":\"a\",\"1\":\"b\"},{\"0\":\"1\",\"1\":\"2\"}]\n");
+}
+
+#[test]
+fn test_csv_output() {
+    oq().args(["-o", "csv", "."])
+        .write_stdin(r#"[{"id": 1, "note": "say \"hi\", bye"}, {"note": "x", "id": 2}]"#)
+        .assert()
+        .success()
+        .stdout("id,note\n1,\"say \"\"hi\"\", bye\"\n2,x\n");
+
+    // Streamed objects share the header of the first one
+    oq().args(["-o", "tsv", "--columns", "age,name", ".[]"])
+        .arg("tests/fixtures/people.csv")
+        .assert()
+        .success()
+        .stdout("age\tname\n36\tAda\n007\tLovelace, B\n");
+
+    // --columns also picks the fields of an array of objects
+    oq().args(["-o", "csv", "--columns", "age,name", "."])
+        .arg("tests/fixtures/people.csv")
+        .assert()
+        .success()
+        .stdout("age,name\n36,Ada\n007,\"Lovelace, B\"\n");
+
+    // One header for all files, however many threads read them
+    let people = "tests/fixtures/people.csv";
+    let rows = "Ada,36,true\n\"Lovelace, B\",007,\n";
+    oq().args(["--threads", "4", ".[]", people, people])
+        .assert()
+        .success()
+        .stdout(format!("name,age,admin\n{rows}{rows}"));
+
+    oq().args(["-o", "csv", "--no-header", "."])
+        .write_stdin(r#"{"a": 1, "b": null}"#)
+        .assert()
+        .success()
+        .stdout("1,\n");
+}
+
+#[test]
+fn test_xml_input() {
+    oq().args(["-r", ".project.dependencies.dependency[].artifactId"])
+        .arg("tests/fixtures/pom.xml")
+        .assert()
+        .success()
+        .stdout("junit\nguava\n");
+
+    oq().args(["-c", "-o", "json", ".project.dependencies.dependency[0]"])
+        .arg("tests/fixtures/pom.xml")
+        .assert()
+        .success()
+        .stdout("{\"@scope\":\"test\",\"artifactId\":\"junit\"}\n");
+}
+
+#[test]
+fn test_xml_round_trip() {
+    let pom = std::fs::read_to_string("tests/fixtures/pom.xml").unwrap();
+    oq().args([".", "tests/fixtures/pom.xml"])
+        .assert()
+        .success()
+        .stdout(pom);
+
+    oq().args(["-o", "xml", "-c", "."])
+        .write_stdin(r##"{"a": {"@id": "1", "#text": "x & y"}}"##)
+        .assert()
+        .success()
+        .stdout("<?xml version=\"1.0\" encoding=\"UTF-8\"?><a id=\"1\">x &amp; y</a>\n");
+
+    // A key that is no element name is an output error, not a filter error
+    oq().args(["-n", "-o", "xml", r#"{"a b": 1}"#])
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(
+            "XML output error: cannot encode `a b`",
+        ));
+}
+
+#[test]
+fn test_ini_input() {
+    oq().args(["-r", ".Unit.After[]", "tests/fixtures/web.service"])
+        .assert()
+        .success()
+        .stdout("network.target\ndb.service\n");
+
+    oq().args(["--infer-types", "-c", "-o", "json", ".Service"])
+        .arg("tests/fixtures/web.service")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"ExecStart\":\"/usr/bin/web --port 8080\",",
+            "\"Restart\":\"on-failure\",\"RestartSec\":5}\n"
+        ));
+
+    // Detected from content: unquoted values are not TOML
+    oq().args(["-r", ".core.editor"])
+        .write_stdin("[core]\n\teditor = vim -f\n[remote \"origin\"]\n\turl = x\n")
+        .assert()
+        .success()
+        .stdout("vim -f\n");
+}
+
+#[test]
+fn test_ini_output() {
+    oq().args(["-o", "ini", "."])
+        .write_stdin(r#"{"name": "a", "db": {"hosts": ["x", "y"], "note": " padded"}}"#)
+        .assert()
+        .success()
+        .stdout("name = a\n\n[db]\nhosts = x\nhosts = y\nnote = \" padded\"\n");
+}
+
+#[test]
+fn test_env_input() {
+    oq().args(["-c", "-o", "json", "."])
+        .arg("tests/fixtures/app.env")
+        .env_remove("OQ_TEST_LOG_LEVEL")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"APP_NAME\":\"oq\",\"DB_HOST\":\"localhost\",\"DB_PORT\":\"5432\",",
+            "\"DB_URL\":\"postgres://localhost:5432/app\",",
+            "\"GREETING\":\"Hello, $USER\",\"LOG_LEVEL\":\"info\"}\n"
+        ));
+
+    // Variables fall back to the environment
+    oq().args(["-r", ".LOG_LEVEL", "tests/fixtures/app.env"])
+        .env("OQ_TEST_LOG_LEVEL", "debug")
+        .assert()
+        .success()
+        .stdout("debug\n");
+
+    // Detected from content
+    oq().args(["-r", ".B"])
+        .write_stdin("A=1\nB=\"two words\"\n")
+        .assert()
+        .success()
+        .stdout("two words\n");
+}
+
+#[test]
+fn test_env_output() {
+    let input =
+        r#"{"db": {"host": "x", "password": "p'w $HOME", "ports": [1, 2]}, "dry-run": true}"#;
+    oq().args(["-o", "env", "."])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(concat!(
+            "DB__HOST=x\nDB__PASSWORD=\"p'w \\$HOME\"\n",
+            "DB__PORTS__0=1\nDB__PORTS__1=2\nDRY_RUN=true\n"
+        ));
+
+    oq().args(["-o", "env", "--export", "--env-separator", "_", ".db"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "export HOST=x\nexport PASSWORD=\"p'w \\$HOME\"\nexport PORTS_0=1\nexport PORTS_1=2\n",
+        );
+
+    // The output reads back as the same values
+    let output = oq()
+        .args(["-o", "env", ".db"])
+        .write_stdin(input)
+        .output()
+        .unwrap();
+    oq().args(["-i", "env", "-r", ".PASSWORD"])
+        .write_stdin(output.stdout)
+        .assert()
+        .success()
+        .stdout("p'w $HOME\n");
+
+    // Anything but an object is an error, not text for `eval`
+    for filter in [".db.password", ".db.ports"] {
+        oq().args(["-o", "env", filter])
+            .write_stdin(input)
+            .assert()
+            .code(6)
+            .stdout("")
+            .stderr(predicate::str::contains(
+                "ENV output error: needs an object",
+            ));
+    }
+}
+
+#[test]
+fn test_jsonc_input() {
+    // A .json file with comments and trailing commas is read leniently and
+    // written back as plain JSON
+    oq().args(["-c", ".", "tests/fixtures/tsconfig.json"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"compilerOptions\":{\"target\":\"es2022\",\"strict\":true,",
+            "\"paths\":{\"@/*\":[\"./src/*\"]}},\"include\":[\"src\",\"tests\"]}\n"
+        ));
+
+    // Detected from content when strict JSON fails
+    oq().args(["-r", ".b"])
+        .write_stdin("{a: 1, b: 'two', /* done */}")
+        .assert()
+        .success()
+        .stdout("two\n");
+
+    // An explicit -i json stays strict
+    oq().args(["-i", "json", ".", "tests/fixtures/tsconfig.json"])
+        .assert()
+        .code(6);
+}
+
+#[test]
+fn test_json5_output() {
+    oq().args(["-o", "json5", "."])
+        .write_stdin(r#"{"name": "oq", "two words": [1], "@scope": {"$id": null}}"#)
+        .assert()
+        .success()
+        .stdout("{\n  name: \"oq\",\n  \"two words\": [\n    1\n  ],\n  \"@scope\": {\n    $id: null\n  }\n}\n");
+
+    oq().args(["-c", "-o", "json5", ".compilerOptions.paths"])
+        .arg("tests/fixtures/tsconfig.json")
+        .assert()
+        .success()
+        .stdout("{\"@/*\":[\"./src/*\"]}\n");
+}